@@ -1,6 +1,9 @@
 use std::path::PathBuf;
 
-use clap::{Args, Parser, Subcommand, ValueEnum};
+use clap::{Parser, Subcommand};
+
+use crate::compression::Codec;
+use crate::object_id::ObjectFormat;
 
 
 #[derive(Debug, Parser)]
@@ -13,7 +16,12 @@ pub struct Warp {
 
 #[derive(Debug, Subcommand)]
 pub enum Commands {
-    Init,
+    Init {
+        #[arg(long, default_value_t = ObjectFormat::Sha1, help = "Hash function used to name objects (sha1 or sha256)")]
+        object_format: ObjectFormat,
+        #[arg(long, default_value_t = Codec::default(), help = "Codec for loose objects (zlib or zstd)")]
+        compression: Codec
+    },
     Hash {
         #[arg(required = true)]
         path: PathBuf
@@ -22,17 +30,28 @@ pub enum Commands {
         #[arg(required = true)]
         path: Vec<PathBuf>
     },
+    #[command(group(clap::ArgGroup::new("action").required(true).multiple(true).args(["add", "index_info", "index_version"])))]
     UpdateIndex {
         #[arg(
             long,
             value_name = "FILENAME",
-            required = true,
             num_args = 1..,
             help = "Stage a file"
         )]
-        add: Vec<PathBuf>
+        add: Vec<PathBuf>,
+        #[arg(long, help = "Read `<mode> <object> <stage>\t<path>` lines from stdin, as merges record conflicts")]
+        index_info: bool,
+        #[arg(long, value_name = "N", help = "Rewrite the index in format version 2, 3 or 4")]
+        index_version: Option<u32>
     },
     WriteTree,
+    /// Show the paths in the index
+    LsFiles {
+        #[arg(short = 's', long, help = "Show the mode, object and stage of each entry")]
+        stage: bool,
+        #[arg(short = 'u', long, help = "Show only conflicted entries (implies --stage)")]
+        unmerged: bool
+    },
     CommitTree {
         tree: String,
         #[arg(
@@ -42,7 +61,7 @@ pub enum Commands {
             required = false,
             help = "ID of parent commit object"
         )]
-        parents: Option<String>,
+        parents: Vec<String>,
         #[arg(
             short = 'm',
             long,
@@ -56,4 +75,117 @@ pub enum Commands {
         #[arg(required = true)]
         path: PathBuf
     },
+    /// Pack all reachable objects and remove the redundant loose files
+    Gc {
+        #[arg(long, default_value_t = 10, help = "Number of preceding objects to try as delta bases")]
+        window: usize,
+        #[arg(long, default_value_t = 50, help = "Maximum length of a delta chain")]
+        depth: usize
+    },
+    /// Create, list or delete tags
+    Tag {
+        #[arg(short = 'a', long, help = "Make an annotated tag object")]
+        annotate: bool,
+        #[arg(short = 'm', long, value_name = "message", help = "Tag message (implies -a)")]
+        message: Option<String>,
+        #[arg(short = 'd', long, help = "Delete the named tags")]
+        delete: bool,
+        #[arg(short = 'l', long, help = "List tags")]
+        list: bool,
+        #[arg(short = 'f', long, help = "Replace an existing tag")]
+        force: bool,
+        #[arg(long, value_name = "object", help = "List only tags that point at the given object")]
+        points_at: Option<String>,
+        #[arg(help = "Tag name and the object it should point at (HEAD by default)")]
+        names: Vec<String>
+    },
+    /// Remove unreachable loose objects older than the expiry date
+    Prune {
+        #[arg(long, value_name = "date", default_value = "2.weeks.ago", help = "Only prune objects last written before this date")]
+        expire: String,
+        #[arg(short = 'n', long, help = "List the objects that would be pruned without removing them")]
+        dry_run: bool
+    },
+    /// Import the objects, refs and HEAD of a local git repository
+    ImportGit {
+        #[arg(required = true, help = "Path to the .git directory (or its working tree)")]
+        path: PathBuf
+    },
+    /// Write the history of refs as a git fast-import stream to stdout
+    FastExport {
+        #[arg(help = "Refs to export (all refs by default)")]
+        refs: Vec<String>
+    },
+    /// Read a git fast-import stream from stdin
+    FastImport,
+    /// Move history between repositories as a single bundle file
+    Bundle {
+        #[command(subcommand)]
+        command: BundleCommand
+    },
+    /// Write files from the index to the working tree, expanding LFS pointers
+    CheckoutIndex {
+        #[arg(short = 'a', long, help = "Check out every file in the index")]
+        all: bool,
+        #[arg(short = 'f', long, help = "Overwrite existing files")]
+        force: bool,
+        #[arg(required_unless_present = "all")]
+        paths: Vec<PathBuf>
+    },
+    /// Move large file contents between local LFS stores
+    Lfs {
+        #[command(subcommand)]
+        command: LfsCommand
+    },
+    /// Verify the connectivity and validity of the objects and the index
+    Fsck,
+    #[command(group(clap::ArgGroup::new("mode").required(true).args(["kind", "size", "pretty", "exists"])))]
+    CatFile {
+        #[arg(short = 't', help = "Show the object type")]
+        kind: bool,
+        #[arg(short = 's', help = "Show the object size")]
+        size: bool,
+        #[arg(short = 'p', help = "Pretty-print the object content")]
+        pretty: bool,
+        #[arg(short = 'e', help = "Exit with zero status if the object exists")]
+        exists: bool,
+        object: String
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum BundleCommand {
+    /// Write the objects for a revision range and its refs into a bundle file
+    Create {
+        #[arg(required = true)]
+        file: PathBuf,
+        #[arg(long, help = "Bundle every ref")]
+        all: bool,
+        #[arg(help = "Refs to bundle, with `^rev` or `rev..ref` to leave out history the receiver has")]
+        revisions: Vec<String>
+    },
+    /// Check that a bundle is intact and its prerequisites are present
+    Verify {
+        #[arg(required = true)]
+        file: PathBuf
+    },
+    /// Import the objects of a bundle and print the refs it provides
+    Unbundle {
+        #[arg(required = true)]
+        file: PathBuf
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum LfsCommand {
+    /// Copy missing large files from another repository's LFS store
+    Fetch {
+        #[arg(required = true, help = "Path to the other repository")]
+        remote: PathBuf
+    },
+    /// Copy large files to another repository's LFS store
+    Push {
+        #[arg(required = true, help = "Path to the other repository")]
+        remote: PathBuf
+    },
 }
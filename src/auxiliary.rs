@@ -1,6 +1,104 @@
-use std::{env, fs, path::{Path, PathBuf}};
+use std::{env, fs, io, path::{Path, PathBuf}};
 
-use crate::commands::hash_object;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+
+use crate::blob::Blob;
+
+// The `.warp` directory of the repository in the current working directory.
+pub fn warp_dir() -> PathBuf {
+    let mut root = env::current_dir().expect("Unable to get cwd");
+    root.push(".warp");
+    root
+}
+
+// Parse an expiry date such as `now`, `never`, `2.weeks.ago`, `3 days ago`,
+// `2024-01-31`, `2024-01-31 12:00:00`, an RFC 3339 date or a unix timestamp
+// into seconds since the epoch. `never` yields `None`.
+pub fn parse_expiry(value: &str, now: i64) -> io::Result<Option<i64>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("unable to parse expiry date '{}'", value));
+    let value = value.trim();
+
+    match value {
+        "never" | "false" => return Ok(None),
+        "now" | "all" => return Ok(Some(now)),
+        _ => {},
+    }
+    if value.bytes().all(|b| b.is_ascii_digit()) {
+        return value.parse().map(Some).map_err(|_| invalid());
+    }
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Ok(Some(date.timestamp()));
+    }
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"] {
+        if let Ok(date) = NaiveDateTime::parse_from_str(value, format) {
+            return Local.from_local_datetime(&date).earliest().map(|date| Some(date.timestamp())).ok_or_else(invalid);
+        }
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let midnight = date.and_hms_opt(0, 0, 0).expect("midnight is a valid time");
+        return Local.from_local_datetime(&midnight).earliest().map(|date| Some(date.timestamp())).ok_or_else(invalid);
+    }
+
+    // `<count>.<unit>.ago`, with dots or spaces between the words.
+    let words: Vec<&str> = value.split(|c: char| c == '.' || c.is_whitespace()).filter(|word| !word.is_empty()).collect();
+    let [count, unit, "ago"] = words.as_slice() else {
+        return Err(invalid());
+    };
+    let count: i64 = count.parse().map_err(|_| invalid())?;
+    let seconds = match unit.trim_end_matches('s') {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 60 * 60,
+        "day" => 24 * 60 * 60,
+        "week" => 7 * 24 * 60 * 60,
+        "month" => 30 * 24 * 60 * 60,
+        "year" => 365 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    Ok(Some(now - count * seconds))
+}
+
+// Shell-style matching of `*` and `?`, as used by `tag -l <pattern>` and
+// the `lfs.track` patterns.
+pub fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| glob_match(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && glob_match(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && glob_match(rest, &name[1..]),
+    }
+}
+
+// Where to write the index path `path` below the working tree `root`. Index
+// paths can come from anyone's history, so any path that is not plainly
+// relative (empty, `.` or `..` components, or absolute), that points into
+// `.warp`, or whose existing parent directories include a symlink is
+// refused rather than written outside the working tree.
+pub fn checkout_path(root: &Path, path: &str) -> io::Result<PathBuf> {
+    let refuse = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, format!("refusing to check out '{}': {}", path, reason));
+    let components: Vec<&str> = path.split('/').collect();
+    if components.iter().any(|component| component.is_empty() || *component == "." || *component == "..") {
+        return Err(refuse("not a plain relative path"));
+    }
+    if components.iter().any(|component| component.eq_ignore_ascii_case(".warp")) {
+        return Err(refuse("inside the .warp directory"));
+    }
+
+    let mut target = root.to_path_buf();
+    for component in &components[..components.len() - 1] {
+        target.push(component);
+        match fs::symlink_metadata(&target) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                return Err(refuse(&format!("{} is a symbolic link", target.display())));
+            },
+            Ok(_) => {},
+            // The rest of the directories are yet to be created.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(root.join(path))
+}
 
 // Create a file from the path endpoint
 pub fn push_path(mut path: PathBuf, end_path: &str) {
@@ -8,15 +106,6 @@ pub fn push_path(mut path: PathBuf, end_path: &str) {
     fs::File::create(path).unwrap();
 }
 
-// Create a single directory with a single file inside it(in Objects)
-pub fn push_dir_with_file(mut path: PathBuf, dir: &str, file_name: &str) {
-    path.push(dir);
-    if !path.is_dir() {
-        fs::create_dir(&path).expect("Error creating this directory");
-    }
-    push_path(path, file_name);
-}
-
 // Create directories with subsequent subdirectories
 pub fn push_recursive_dir(mut path: PathBuf, dir: &str, end_points: Vec<&str>) {
     path.push(dir);
@@ -37,22 +126,19 @@ pub fn push_recursive_dir(mut path: PathBuf, dir: &str, end_points: Vec<&str>) {
 
 pub fn traverse_directory(path: &Path) {
     if path.is_dir() && path.file_name().unwrap() != ".warp" {
-        let mut tree = path.file_name().unwrap();
+        let tree = path.file_name().unwrap();
         let mut tree_content: Vec<PathBuf> = Vec::new();
         if let Ok(entries) = fs::read_dir(path) {
-            for entry in entries {
-                if let Ok(entry) = entry {
-                    let entry_path = entry.path();
-
-                    println!("Entry {:?} in dir {:?}", entry_path.file_name().unwrap(), path.file_name().unwrap());
-                    if entry_path.is_file() {
-                        let hash = hash_object(entry_path.clone()).expect("Error calculating hash of a file");
-                        get_object_file_hash(hash);
-                        tree_content.push(entry_path.clone());
-                    } else if entry_path.is_dir() {
-                        traverse_directory(&entry_path);
-                        tree_content.push(entry_path);
-                    }
+            for entry in entries.flatten() {
+                let entry_path = entry.path();
+
+                println!("Entry {:?} in dir {:?}", entry_path.file_name().unwrap(), path.file_name().unwrap());
+                if entry_path.is_file() {
+                    Blob::new(entry_path.clone()).compress_to_object().expect("Error writing blob object");
+                    tree_content.push(entry_path.clone());
+                } else if entry_path.is_dir() {
+                    traverse_directory(&entry_path);
+                    tree_content.push(entry_path);
                 }
             }
 
@@ -63,30 +149,51 @@ pub fn traverse_directory(path: &Path) {
     }
 }
 
-fn get_object_file_hash(file_hash: String) {
-    // Create an object directory and file for this file_hash
-    let mut root = env::current_dir().unwrap();
-    root.push(".warp");
-    root.push("objects");
-
-    let (dir_name, content) = file_hash.split_at(2);
-    push_dir_with_file(root, dir_name, content);
-
-}
-
 pub fn file_exists(path: &PathBuf, file_name: &str) -> bool {
     let mut file_set = false;
 
-    if let Ok(entries) = fs::read_dir(&path) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                if entry.file_name() == file_name {
-                    file_set = true;
-                    break;
-                }
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.flatten() {
+            if entry.file_name() == file_name {
+                file_set = true;
+                break;
             }
         }
     }
 
     file_set
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_expiry() {
+        let now = 1_700_000_000;
+        assert_eq!(parse_expiry("now", now).unwrap(), Some(now));
+        assert_eq!(parse_expiry("never", now).unwrap(), None);
+        assert_eq!(parse_expiry("2.weeks.ago", now).unwrap(), Some(now - 14 * 24 * 60 * 60));
+        assert_eq!(parse_expiry("1 hour ago", now).unwrap(), Some(now - 60 * 60));
+        assert_eq!(parse_expiry("2024-01-31T12:00:00+00:00", now).unwrap(), Some(1_706_702_400));
+        assert_eq!(parse_expiry("1600000000", now).unwrap(), Some(1_600_000_000));
+        assert!(parse_expiry("2.fortnights.ago", now).is_err());
+    }
+
+    #[test]
+    fn test_checkout_path() {
+        let dir = std::env::temp_dir().join(format!("warp-checkout-path-{}", std::process::id()));
+        fs::create_dir_all(dir.join("real")).unwrap();
+        std::os::unix::fs::symlink("/tmp", dir.join("link")).unwrap();
+
+        assert_eq!(checkout_path(&dir, "real/new/file").unwrap(), dir.join("real/new/file"));
+        for path in ["../escape", "/etc/passwd", "a//b", "a/./b", "", ".warp/config", "sub/.WARP/hooks"] {
+            assert!(checkout_path(&dir, path).is_err(), "{} was accepted", path);
+        }
+        assert!(checkout_path(&dir, "link/file").is_err());
+        // A symlink is fine as the file itself, just not as a directory.
+        assert!(checkout_path(&dir, "link").is_ok());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,10 +1,9 @@
-use std::env;
-use std::io::Write;
-use std::{ffi::CString, fs, io::Read, path::PathBuf};
+use std::{fs, io, path::PathBuf};
 
-use sha1::{Digest, Sha1};
-use flate2::Compression;
-use flate2::write::ZlibEncoder;
+use crate::chunking::chunk_file;
+use crate::lfs::clean_file;
+use crate::object_id::ObjectId;
+use crate::object_store::{ObjectKind, ObjectStore};
 
 
 #[derive(Clone)]
@@ -20,58 +19,40 @@ impl Blob {
         }
     }
 
-    fn hash_content(&self) -> Vec<u8> {
-        let mut file = fs::File::open(&self.filename).unwrap();
-
-        let mut buf = String::new();
-        file.read_to_string(&mut buf).unwrap();
-
-        let blob = buf.as_bytes();
-        let blob_len = blob.len();
-
-        let header = format!("blob {}", blob_len);
-        let header= CString::new(header).expect("CString failed");
-
-        let header_bytes = header.as_bytes_with_nul();
-        let hash_object = [header_bytes, blob].concat();
-
-        hash_object
-        
+    // Open the file along with the size recorded in the object header.
+    fn open(&self) -> io::Result<(fs::File, u64)> {
+        let file = fs::File::open(&self.filename)?;
+        let size = file.metadata()?.len();
+        Ok((file, size))
     }
 
-    // Hash a file producing an object file.
+    // Hash a file producing an object file. The content is streamed as raw
+    // bytes, so binary files of any size are fine. Files tracked by the LFS
+    // rules hash as their pointer, and chunked files as their manifest.
     pub fn hash_object(&self) -> Result<String, Box<dyn std::error::Error>> {
-        let mut hasher = Sha1::new();
-        let hash_object = self.hash_content();
-        
-        hasher.update(hash_object);
-        let result = hasher.finalize();
-        Ok(hex::encode(result))
+        let store = ObjectStore::open()?;
+        if let Some(pointer) = clean_file(&self.filename, false)? {
+            return Ok(store.hash(ObjectKind::Blob, &pointer.to_bytes()).to_hex());
+        }
+        if let Some(manifest) = chunk_file(&store, &self.filename, false)? {
+            return Ok(store.hash(ObjectKind::Blob, &manifest.to_bytes()).to_hex());
+        }
+        let (file, size) = self.open()?;
+        Ok(store.hash_stream(ObjectKind::Blob, size, file)?.to_hex())
     }
 
-    pub fn compress_to_object(&self) {
-        // Get the blob contents
-        let hash = self.hash_content();
-
-        // Compress the blob content
-        let mut encoded = ZlibEncoder::new(Vec::new(), Compression::default());
-        encoded.write_all(&hash).expect("Error writing to compressing stream");
-        let compressed_bytes = encoded.finish().expect("Error compressing hash content");
-        
-        // Create object Identifier from filename;
-        let object = self.hash_object().expect("Error hashing file object");
-
-        // Get the root_directory and create object tree 
-        let mut root = env::current_dir().expect("Unable to get cwd");
-        root.push(".warp");
-        root.push("objects");
-        let (dir_hash, file_hash) = object.split_at(2);
-        crate::auxiliary::push_dir_with_file(root.clone(), dir_hash, file_hash);
-
-        root.push(dir_hash);
-        root.push(file_hash);
-
-        let mut compressed_file = fs::File::create(root).expect("Unable to open file");
-        compressed_file.write(&compressed_bytes).expect("Error writing to file");
+    // Store the file as a blob. Files tracked by the LFS rules move into the
+    // LFS store and files above the chunking threshold are stored as chunks;
+    // either way the blob written is the small description of the content.
+    pub fn compress_to_object(&self) -> io::Result<ObjectId> {
+        let store = ObjectStore::open()?;
+        if let Some(pointer) = clean_file(&self.filename, true)? {
+            return store.write(ObjectKind::Blob, &pointer.to_bytes());
+        }
+        if let Some(manifest) = chunk_file(&store, &self.filename, true)? {
+            return store.write(ObjectKind::Blob, &manifest.to_bytes());
+        }
+        let (file, size) = self.open()?;
+        store.write_stream(ObjectKind::Blob, size, file)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,208 @@
+use std::{collections::HashSet, fs, io::{self, BufRead, BufWriter, Write}, path::Path};
+
+use crate::object::Object;
+use crate::object_id::{ObjectFormat, ObjectId};
+use crate::object_store::{ObjectKind, ObjectStore};
+use crate::pack::{write_pack_to, PackOptions};
+use crate::reachable::walk;
+use crate::refs::{resolve_revision, select_refs, Refs};
+
+pub const BUNDLE_V2_SIGNATURE: &str = "# v2 git bundle";
+pub const BUNDLE_V3_SIGNATURE: &str = "# v3 git bundle";
+
+fn invalid_bundle(path: &Path, reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), reason))
+}
+
+// The header of a git bundle: the commits the receiving repository must
+// already have, and the refs the bundled pack provides. The pack follows
+// the header directly.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bundle {
+    pub format: ObjectFormat,
+    pub prerequisites: Vec<(ObjectId, String)>,
+    pub refs: Vec<(ObjectId, String)>
+}
+
+impl Bundle {
+    // Parse a header up to and including the blank line that ends it.
+    pub fn read_header<R: BufRead>(reader: &mut R, path: &Path) -> io::Result<Self> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = Vec::new();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                return Err(invalid_bundle(path, "truncated header"));
+            }
+            if line == b"\n" {
+                break;
+            }
+            let line = String::from_utf8(line).map_err(|_| invalid_bundle(path, "header is not valid UTF-8"))?;
+            lines.push(line.trim_end_matches('\n').to_string());
+        }
+
+        let mut lines = lines.into_iter().peekable();
+        let version = lines.next().unwrap_or_default();
+        if version != BUNDLE_V2_SIGNATURE && version != BUNDLE_V3_SIGNATURE {
+            return Err(invalid_bundle(path, "not a v2 or v3 git bundle"));
+        }
+
+        let mut format = ObjectFormat::Sha1;
+        while let Some(capability) = lines.next_if(|line| line.starts_with('@')) {
+            match capability[1..].split_once('=') {
+                Some(("object-format", value)) => format = value.parse()?,
+                _ => return Err(invalid_bundle(path, &format!("unsupported capability '{}'", capability))),
+            }
+        }
+
+        let mut bundle = Bundle { format, prerequisites: Vec::new(), refs: Vec::new() };
+        for line in lines {
+            let (target, rest) = match line.strip_prefix('-') {
+                Some(prerequisite) => (&mut bundle.prerequisites, prerequisite),
+                None => (&mut bundle.refs, line.as_str()),
+            };
+            let (oid, name) = rest.split_once(' ').unwrap_or((rest, ""));
+            let oid: ObjectId = oid.parse().map_err(|_| invalid_bundle(path, &format!("bad header line '{}'", line)))?;
+            if oid.format() != format {
+                return Err(invalid_bundle(path, &format!("{} is not a {} object id", oid, format)));
+            }
+            target.push((oid, name.to_string()));
+        }
+        Ok(bundle)
+    }
+
+    // Version 2 only knows SHA-1; anything else needs a v3 capability.
+    pub fn write_header<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        match self.format {
+            ObjectFormat::Sha1 => writeln!(out, "{}", BUNDLE_V2_SIGNATURE)?,
+            format => writeln!(out, "{}\n@object-format={}", BUNDLE_V3_SIGNATURE, format)?,
+        }
+        for (oid, comment) in &self.prerequisites {
+            writeln!(out, "-{} {}", oid, comment)?;
+        }
+        for (oid, name) in &self.refs {
+            writeln!(out, "{} {}", oid, name)?;
+        }
+        writeln!(out)
+    }
+
+    pub fn missing_prerequisites(&self, store: &ObjectStore) -> Vec<ObjectId> {
+        self.prerequisites.iter().map(|(oid, _)| *oid).filter(|oid| !store.contains(oid)).collect()
+    }
+}
+
+// Read a bundle file into its header and the raw pack after it.
+pub fn read(path: &Path) -> io::Result<(Bundle, Vec<u8>)> {
+    let data = fs::read(path)?;
+    let mut reader = data.as_slice();
+    let bundle = Bundle::read_header(&mut reader, path)?;
+    Ok((bundle, reader.to_vec()))
+}
+
+// Write a bundle of everything reachable from the included revisions but
+// not from the excluded ones. `a..b` includes `b` and excludes `a`, `^a`
+// excludes `a`; every included revision must name a ref. Excluded commits
+// that the bundled history builds on become prerequisites.
+pub fn create(store: &ObjectStore, refs: &Refs, path: &Path, revisions: &[String], all: bool) -> io::Result<Bundle> {
+    let mut included = Vec::new();
+    let mut excluded = Vec::new();
+    for revision in revisions {
+        if let Some((from, to)) = revision.split_once("..") {
+            excluded.push(if from.is_empty() { "HEAD" } else { from }.to_string());
+            included.push(if to.is_empty() { "HEAD" } else { to }.to_string());
+        } else if let Some(name) = revision.strip_prefix('^') {
+            excluded.push(name.to_string());
+        } else {
+            included.push(revision.clone());
+        }
+    }
+
+    let mut bundle_refs = if all { refs.list()? } else { Vec::new() };
+    if !included.is_empty() {
+        bundle_refs.extend(select_refs(refs, &included)?);
+    }
+    // `--all` and an explicit ref can name the same ref twice.
+    bundle_refs.sort();
+    bundle_refs.dedup();
+    if bundle_refs.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "refusing to create a bundle without refs"));
+    }
+
+    let excluded = excluded.iter().map(|name| resolve_revision(store, refs, name)).collect::<io::Result<Vec<_>>>()?;
+    let excluded: HashSet<ObjectId> = walk(store, &excluded)?.into_iter().map(|object| object.oid).collect();
+    let tips: Vec<ObjectId> = bundle_refs.iter().map(|(_, oid)| *oid).collect();
+    let objects: Vec<_> = walk(store, &tips)?.into_iter().filter(|object| !excluded.contains(&object.oid)).collect();
+    if objects.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "refusing to create an empty bundle"));
+    }
+
+    let mut prerequisites = Vec::new();
+    for object in objects.iter().filter(|object| object.kind == ObjectKind::Commit) {
+        let Object::Commit(commit) = Object::read(store, &object.oid)? else { continue };
+        for parent in commit.parents.iter().filter(|parent| excluded.contains(*parent)) {
+            if prerequisites.iter().any(|(oid, _)| oid == parent) {
+                continue;
+            }
+            let subject = match Object::read(store, parent)? {
+                Object::Commit(parent) => String::from_utf8_lossy(&parent.message).lines().next().unwrap_or_default().to_string(),
+                _ => String::new(),
+            };
+            prerequisites.push((*parent, subject));
+        }
+    }
+
+    let bundle = Bundle {
+        format: store.format(),
+        prerequisites,
+        refs: bundle_refs.into_iter().map(|(name, oid)| (oid, name)).collect()
+    };
+    let mut out = BufWriter::new(fs::File::create(path)?);
+    bundle.write_header(&mut out)?;
+    write_pack_to(store, &objects, &PackOptions::default(), &mut out)?;
+    out.flush()?;
+    Ok(bundle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commit::Commit;
+    use crate::tree::{Tree, TreeEntry};
+
+    #[test]
+    fn test_header_round_trip() {
+        let tip: ObjectId = "ce013625030ba8dba906f756967f9e9ca394464a".parse().unwrap();
+        let base: ObjectId = "4b825dc642cb6eb9a060e54bf8d69288fbee4904".parse().unwrap();
+        let bundle = Bundle {
+            format: ObjectFormat::Sha1,
+            prerequisites: vec![(base, "Initial commit".to_string())],
+            refs: vec![(tip, "refs/heads/master".to_string())]
+        };
+
+        let mut header = Vec::new();
+        bundle.write_header(&mut header).unwrap();
+        assert_eq!(String::from_utf8_lossy(&header), format!("# v2 git bundle\n-{} Initial commit\n{} refs/heads/master\n\n", base, tip));
+
+        header.extend(b"PACK");
+        let mut reader = header.as_slice();
+        assert_eq!(Bundle::read_header(&mut reader, Path::new("test.bundle")).unwrap(), bundle);
+        assert_eq!(reader, b"PACK");
+    }
+
+    #[test]
+    fn test_create_lists_each_ref_once() {
+        let dir = std::env::temp_dir().join(format!("warp-bundle-{}", std::process::id()));
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        let store = ObjectStore::new(dir.join("objects"), ObjectFormat::Sha1);
+        let refs = Refs::new(dir.clone());
+        let blob = store.write(ObjectKind::Blob, b"hello\n").unwrap();
+        let tree = store.write(ObjectKind::Tree, &Tree { entries: vec![TreeEntry::new(0o100644, b"a.txt", blob)] }.to_bytes()).unwrap();
+        let commit = store.write(ObjectKind::Commit, &Commit::new(tree, Vec::new(), "first".to_string()).to_bytes()).unwrap();
+        refs.update("refs/tags/v1", &commit).unwrap();
+        refs.update("refs/heads/master", &commit).unwrap();
+
+        let bundle = create(&store, &refs, &dir.join("all.bundle"), &["master".to_string(), "v1".to_string()], true).unwrap();
+        assert_eq!(bundle.refs, vec![(commit, "refs/heads/master".to_string()), (commit, "refs/tags/v1".to_string())]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
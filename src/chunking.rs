@@ -0,0 +1,281 @@
+use std::{fs, io::{self, Read, Write}, path::Path};
+
+use crate::config::Config;
+use crate::object_id::ObjectId;
+use crate::object_store::{ObjectKind, ObjectStore};
+
+// Every manifest starts with this reserved magic. It begins with a NUL so
+// no text file can look like a manifest, and files that do start with it
+// are always stored chunked, so a blob with this prefix is a manifest.
+pub const MANIFEST_MAGIC: &[u8] = b"\0warp chunked blob\0v1\n";
+
+pub const DEFAULT_AVERAGE_CHUNK: usize = 64 * 1024;
+
+// Smallest average chunk size accepted, so that manifests stay small
+// relative to the content they describe.
+const MIN_AVERAGE_CHUNK: usize = 1024;
+
+// FastCDC's gear table: one pseudo-random value per byte, generated with
+// splitmix64 from a fixed seed so that chunk boundaries never change
+// between builds.
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x5741_5250_4344_4331;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+// Chunk size bounds. Cut points are found with a gear rolling hash, using
+// a stricter mask before the average size and a looser one after it
+// (FastCDC's normalized chunking), so sizes cluster around the average.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkParams {
+    pub min: usize,
+    pub average: usize,
+    pub max: usize
+}
+
+impl ChunkParams {
+    pub fn new(average: usize) -> io::Result<Self> {
+        if average < MIN_AVERAGE_CHUNK {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("average chunk size must be at least {} bytes", MIN_AVERAGE_CHUNK)));
+        }
+        Ok(Self { min: average / 4, average, max: average * 4 })
+    }
+
+    // Masks over the high bits of the hash, which depend on the most bytes.
+    fn masks(&self) -> (u64, u64) {
+        let bits = self.average.ilog2();
+        let mask = |bits: u32| ((1u64 << bits) - 1) << (64 - bits);
+        (mask(bits + 1), mask(bits - 1))
+    }
+
+    // Length of the chunk at the start of `data`, which holds at least
+    // `max` bytes unless it is the end of the input.
+    pub fn cut(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min {
+            return data.len();
+        }
+        let end = data.len().min(self.max);
+        let normal = end.min(self.average);
+        let (strict, loose) = self.masks();
+
+        let mut hash = 0u64;
+        for (i, byte) in data.iter().enumerate().take(end).skip(self.min) {
+            hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+            let mask = if i < normal { strict } else { loose };
+            if hash & mask == 0 {
+                return i + 1;
+            }
+        }
+        end
+    }
+}
+
+impl Default for ChunkParams {
+    fn default() -> Self {
+        Self::new(DEFAULT_AVERAGE_CHUNK).expect("default chunk size is valid")
+    }
+}
+
+// Splits a reader into content-defined chunks, holding at most one
+// maximum-size chunk in memory.
+pub struct Chunker<R: Read> {
+    reader: R,
+    params: ChunkParams,
+    buf: Vec<u8>,
+    eof: bool
+}
+
+impl<R: Read> Chunker<R> {
+    pub fn new(reader: R, params: ChunkParams) -> Self {
+        Self { reader, params, buf: Vec::with_capacity(params.max), eof: false }
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        while !self.eof && self.buf.len() < self.params.max {
+            let start = self.buf.len();
+            self.buf.resize(self.params.max, 0);
+            let read = self.reader.read(&mut self.buf[start..])?;
+            self.buf.truncate(start + read);
+            self.eof = read == 0;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for Chunker<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = self.fill() {
+            return Some(Err(e));
+        }
+        if self.buf.is_empty() {
+            return None;
+        }
+        let cut = self.params.cut(&self.buf);
+        Some(Ok(self.buf.drain(..cut).collect()))
+    }
+}
+
+// The blob stored in place of a chunked file: the magic, the total size,
+// then one `<chunk blob id> <length>` line per chunk in order. Trees list it
+// with the file's usual mode, so they stay valid for git.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    pub size: u64,
+    pub chunks: Vec<(ObjectId, u64)>
+}
+
+impl Manifest {
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, format!("invalid chunk manifest: {}", reason));
+        let rest = bytes.strip_prefix(MANIFEST_MAGIC).ok_or_else(|| invalid("missing header"))?;
+        let mut lines = std::str::from_utf8(rest).map_err(|_| invalid("not valid UTF-8"))?.lines();
+        let size = lines.next().and_then(|line| line.strip_prefix("size ")).and_then(|size| size.parse().ok())
+            .ok_or_else(|| invalid("missing size"))?;
+
+        let mut chunks = Vec::new();
+        for line in lines {
+            let chunk = line.split_once(' ').and_then(|(oid, length)| Some((oid.parse().ok()?, length.parse().ok()?)));
+            chunks.push(chunk.ok_or_else(|| invalid(&format!("malformed chunk line '{}'", line)))?);
+        }
+        if chunks.iter().map(|(_, length)| length).sum::<u64>() != size {
+            return Err(invalid("chunk lengths do not add up to the size"));
+        }
+        Ok(Self { size, chunks })
+    }
+
+    // The manifest a file's blob holds, if it is one. Blobs without the
+    // magic are ordinary content; a blob with it must parse in full.
+    pub fn detect(bytes: &[u8]) -> io::Result<Option<Self>> {
+        if !bytes.starts_with(MANIFEST_MAGIC) {
+            return Ok(None);
+        }
+        Self::parse(bytes).map(Some)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MANIFEST_MAGIC.to_vec();
+        bytes.extend_from_slice(format!("size {}\n", self.size).as_bytes());
+        for (oid, length) in &self.chunks {
+            bytes.extend_from_slice(format!("{} {}\n", oid, length).as_bytes());
+        }
+        bytes
+    }
+}
+
+// Split `reader` into chunks and describe them in a manifest. Chunks are
+// stored as ordinary blobs unless only hashing (`write` unset); unchanged
+// chunks of an edited file are found already present and cost nothing.
+pub fn chunk_stream<R: Read>(store: &ObjectStore, reader: R, params: ChunkParams, write: bool) -> io::Result<Manifest> {
+    let mut manifest = Manifest { size: 0, chunks: Vec::new() };
+    for chunk in Chunker::new(reader, params) {
+        let chunk = chunk?;
+        let oid = match write {
+            true => store.write(ObjectKind::Blob, &chunk)?,
+            false => store.hash(ObjectKind::Blob, &chunk),
+        };
+        manifest.size += chunk.len() as u64;
+        manifest.chunks.push((oid, chunk.len() as u64));
+    }
+    Ok(manifest)
+}
+
+// Write the content a manifest describes.
+pub fn expand<W: Write>(store: &ObjectStore, manifest: &Manifest, out: &mut W) -> io::Result<()> {
+    for (oid, length) in &manifest.chunks {
+        let (_, chunk) = store.read(oid)?;
+        if chunk.len() as u64 != *length {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("chunk {} has {} bytes, the manifest says {}", oid, chunk.len(), length)));
+        }
+        out.write_all(&chunk)?;
+    }
+    Ok(())
+}
+
+// The manifest to store for `path` if it is at least `chunking.threshold`
+// bytes, chunked around `chunking.averageSize` (64k by default). Smaller
+// files, and every file when no threshold is set, are stored whole, except
+// that a file starting with the manifest magic is always chunked so its
+// blob can never be mistaken for a manifest.
+pub fn chunk_file(store: &ObjectStore, path: &Path, write: bool) -> io::Result<Option<Manifest>> {
+    let config = Config::open()?;
+    let threshold = config.get_size("chunking.threshold")?;
+    let large = match threshold {
+        Some(threshold) => fs::metadata(path)?.len() >= threshold,
+        None => false,
+    };
+    if !large && !starts_with_magic(path)? {
+        return Ok(None);
+    }
+
+    let average = config.get_size("chunking.averagesize")?.map_or(DEFAULT_AVERAGE_CHUNK, |size| size as usize);
+    chunk_stream(store, fs::File::open(path)?, ChunkParams::new(average)?, write).map(Some)
+}
+
+fn starts_with_magic(path: &Path) -> io::Result<bool> {
+    let mut start = Vec::with_capacity(MANIFEST_MAGIC.len());
+    fs::File::open(path)?.take(MANIFEST_MAGIC.len() as u64).read_to_end(&mut start)?;
+    Ok(start == MANIFEST_MAGIC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_id::ObjectFormat;
+
+    // Deterministic pseudo-random test content.
+    fn content(len: usize, mut seed: u64) -> Vec<u8> {
+        (0..len).map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as u8
+        }).collect()
+    }
+
+    #[test]
+    fn test_edit_only_changes_nearby_chunks() {
+        let dir = std::env::temp_dir().join(format!("warp-chunking-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let store = ObjectStore::new(dir.clone(), ObjectFormat::Sha1);
+        let params = ChunkParams::new(4096).unwrap();
+
+        let original = content(512 * 1024, 7);
+        let mut edited = original.clone();
+        edited.splice(200_000..200_000, b"inserted bytes".iter().copied());
+
+        let before = chunk_stream(&store, original.as_slice(), params, true).unwrap();
+        let after = chunk_stream(&store, edited.as_slice(), params, true).unwrap();
+        assert_eq!(before.size, original.len() as u64);
+        assert!(before.chunks.iter().all(|(_, length)| *length as usize <= params.max));
+        let changed = after.chunks.iter().filter(|chunk| !before.chunks.contains(chunk)).count();
+        assert!(changed <= 2, "{} of {} chunks changed", changed, after.chunks.len());
+
+        assert_eq!(Manifest::detect(&after.to_bytes()).unwrap(), Some(after.clone()));
+        assert_eq!(Manifest::detect(b"hello\n").unwrap(), None);
+        // Text that merely looks like a manifest, and a manifest whose
+        // chunks do not add up to its size.
+        assert_eq!(Manifest::detect(format!("size 3\n{} 3\n", before.chunks[0].0).as_bytes()).unwrap(), None);
+        let mut wrong_size = after.clone();
+        wrong_size.size += 1;
+        assert!(Manifest::detect(&wrong_size.to_bytes()).is_err());
+        let mut expanded = Vec::new();
+        expand(&store, &after, &mut expanded).unwrap();
+        assert_eq!(expanded, edited);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,20 +1,37 @@
-use std::ffi::CString;
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::env;
 use std::env::VarError;
 use std::fs;
-use std::io::{Error, Read};
-use sha1::{Sha1, Digest};
+use std::io::{self, Error, Write};
+use std::os::unix::fs::PermissionsExt;
 use std::sync::OnceLock;
 use colored::Colorize;
 
-#[allow(dead_code)]
-
-use crate::auxiliary::{push_recursive_dir, push_path, traverse_directory};
+use crate::auxiliary::{checkout_path, parse_expiry, push_recursive_dir, push_path, traverse_directory, warp_dir};
+use crate::blob::Blob;
+use crate::bundle::{self, Bundle};
+use crate::compression::Codec;
+use crate::chunking::{expand, Manifest};
+use crate::config::Config;
+use crate::fast_export::FastExporter;
+use crate::fast_import::FastImporter;
+use crate::git_import::import_repository;
+use crate::fsck;
+use crate::index::read_index;
+use crate::lfs::{reachable_pointers, LfsStore, Pointer};
+use crate::object_id::{ObjectFormat, ObjectId};
+use crate::object_store::{ObjectKind, ObjectStore};
+use crate::pack::{write_pack, PackOptions};
+use crate::pack_reader::{unpack_objects, PackIndex};
+use crate::reachable::{is_expired, prune_unreachable, repository_roots, walk};
+use crate::refs::{resolve_revision, select_refs, Refs};
+use crate::tag::{create_tag, delete_tag, list_tags};
+use crate::tree::Tree;
 
 pub static ROOT: OnceLock<PathBuf> = OnceLock::new();
 
-pub fn init() -> Result<(), Box<dyn std::error::Error>> {
+pub fn init(object_format: ObjectFormat, compression: Codec) -> Result<(), Box<dyn std::error::Error>> {
     // (Importante): Later on, we have to add a feature, where we first scan if .warp exists on an upper tree
     // wherever this function is called on, and restrict it from continuing, since we have a .warp file
     // already initialised. We know to have a way of knowing the root directory(This should be known by 
@@ -33,15 +50,17 @@ pub fn init() -> Result<(), Box<dyn std::error::Error>> {
     let warp_directory = match env::var("WARP_DIR") {
         Ok(key) => {
             warp_dir = key.clone();
-            Ok(fs::create_dir(key).unwrap())
+            fs::create_dir(key).unwrap();
+            Ok(())
         },
         Err(e) => {
             match e {
                 VarError::NotPresent => {
                     warp_dir += ".warp";
-                    Ok(fs::create_dir(".warp").expect("Directory already exists"))
+                    fs::create_dir(".warp").expect("Directory already exists");
+                    Ok(())
                 },
-                VarError::NotUnicode(err_msg) => Err(Error::new(std::io::ErrorKind::Other, err_msg.to_str().unwrap()))
+                VarError::NotUnicode(err_msg) => Err(Error::other(err_msg.to_string_lossy().to_string()))
             }
         }
     };
@@ -61,6 +80,22 @@ pub fn init() -> Result<(), Box<dyn std::error::Error>> {
             push_recursive_dir(root.clone(), "objects", vec!["info", "pack"]);
             push_recursive_dir(root.clone(), "branches", vec![]);
 
+            // Record the object format and codec. SHA-256 and zstd need
+            // format version 1 so that older builds refuse to open them.
+            let mut config = Config::load(root.join("config"))?;
+            let mut extensions = Vec::new();
+            if object_format != ObjectFormat::Sha1 {
+                extensions.push(("extensions.objectformat", object_format.as_str()));
+            }
+            if let Codec::Zstd(_) = compression {
+                extensions.push(("extensions.compression", compression.name()));
+            }
+            config.set("core.repositoryformatversion", if extensions.is_empty() { "0" } else { "1" });
+            for (key, value) in extensions {
+                config.set(key, value);
+            }
+            config.save()?;
+
             // We should probably print something to the terminal here.
             println!("{}", "hint: Using .warp as the default directory for Warp data.".yellow());
             println!("{}", "hint: Feature for customizing default directory should be added soon".yellow());
@@ -77,27 +112,10 @@ pub fn init() -> Result<(), Box<dyn std::error::Error>> {
 
 // We are going to generate a hash for a blob with this function
 pub fn hash_object(args: PathBuf) -> Result<String, Box<dyn std::error::Error>> {
-    let mut hasher = Sha1::new();
-    let mut file = fs::File::open(args).unwrap();
-
-    let mut buf = String::new();
-    file.read_to_string(&mut buf).unwrap();
-    
-    let blob = buf.as_bytes();
-    let blob_len = blob.len();
-
-    let header = format!("blob {}", blob_len);
-    let header = CString::new(header).expect("CString failed");
-
-    let header_bytes = header.as_bytes_with_nul();
-    let hash_object = [header_bytes, blob].concat();
-    
-    hasher.update(hash_object);
-    let result = hasher.finalize();
-    Ok(hex::encode(result))
+    Blob::new(args).hash_object()
 }
 
-pub fn add(args: Vec<PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
+pub fn add(_args: Vec<PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
     // let root = ROOT.get().expect("Unable to get the current working directory");
     let root = env::current_dir().expect("Unable to get the current working directory");
     println!("{:?}", root.file_name().unwrap());
@@ -112,4 +130,426 @@ pub fn add(args: Vec<PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
     traverse_directory(&root);
 
     Ok(())
-}
\ No newline at end of file
+}
+
+// Inspect an object in the store: its type (-t), size (-s), content (-p) or
+// whether it exists at all (-e).
+pub fn cat_file(kind: bool, size: bool, pretty: bool, exists: bool, object: String) -> Result<(), Box<dyn std::error::Error>> {
+    let store = ObjectStore::open()?;
+    let oid = match store.resolve(&object) {
+        Err(e) if exists && e.kind() == io::ErrorKind::NotFound => std::process::exit(1),
+        result => result?,
+    };
+
+    if exists {
+        return Ok(());
+    }
+
+    let (object_kind, content) = store.read(&oid)?;
+    if kind {
+        println!("{}", object_kind);
+    } else if size {
+        println!("{}", content.len());
+    } else if pretty {
+        match object_kind {
+            ObjectKind::Tree => {
+                let mut out = io::stdout().lock();
+                for entry in Tree::parse(&content, store.format())?.entries {
+                    write!(out, "{:06o} {} {}\t", entry.mode, entry.kind(), entry.oid)?;
+                    out.write_all(&entry.name)?;
+                    writeln!(out)?;
+                }
+            },
+            ObjectKind::Blob | ObjectKind::Commit | ObjectKind::Tag => {
+                io::stdout().write_all(&content)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Repack every object reachable from refs, HEAD and the index into a single
+// pack, then delete the loose copies and the old packs. Unreachable objects
+// from the old packs are kept loose until prune expires them.
+pub fn gc(options: PackOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let store = ObjectStore::open()?;
+    let roots = repository_roots(&Refs::open())?;
+    // Objects borrowed from alternates stay where they are.
+    let objects: Vec<_> = walk(&store, &roots)?.into_iter().filter(|object| store.contains_local(&object.oid)).collect();
+
+    if objects.is_empty() {
+        println!("Nothing to pack");
+        return Ok(());
+    }
+
+    let pack_path = write_pack(&store, &objects, &options)?;
+
+    // The new pack only holds reachable objects. Anything else in the old
+    // packs is written out loose, dated like the pack it came from, so that
+    // deleting it is left to prune and its --expire grace period.
+    let reachable: HashSet<ObjectId> = objects.iter().map(|object| object.oid).collect();
+    let mut old_packs = Vec::new();
+    for entry in fs::read_dir(store.pack_dir())? {
+        let path = entry?.path();
+        let is_pack_file = matches!(path.extension().and_then(|ext| ext.to_str()), Some("pack" | "idx"));
+        if is_pack_file && path.file_stem() != pack_path.file_stem() {
+            old_packs.push(path);
+        }
+    }
+    let mut loosened = 0;
+    for idx_path in old_packs.iter().filter(|path| path.extension().is_some_and(|ext| ext == "idx")) {
+        let modified = fs::metadata(idx_path.with_extension("pack"))?.modified()?;
+        for oid in PackIndex::open(idx_path, store.format())?.oids() {
+            if !reachable.contains(&oid) && !store.is_loose(&oid) {
+                store.loosen(&oid, modified)?;
+                loosened += 1;
+            }
+        }
+    }
+    for path in old_packs {
+        fs::remove_file(path)?;
+    }
+
+    let mut removed = 0;
+    for object in &objects {
+        if store.is_loose(&object.oid) {
+            store.remove_loose(&object.oid)?;
+            removed += 1;
+        }
+    }
+
+    println!("Packed {} objects into {}", objects.len(), pack_path.display());
+    println!("Removed {} loose objects", removed);
+    if loosened > 0 {
+        println!("Kept {} unreachable objects loose for prune", loosened);
+    }
+    Ok(())
+}
+
+// Check every object and the index, print dangling objects and exit with a
+// non-zero status if anything is corrupt or missing.
+pub fn fsck() -> Result<(), Box<dyn std::error::Error>> {
+    let store = ObjectStore::open()?;
+    let report = fsck::check(&store, &Refs::open())?;
+
+    for (kind, oid) in &report.dangling {
+        println!("dangling {} {}", kind, oid);
+    }
+    for warning in &report.warnings {
+        eprintln!("{} {}", "warning:".yellow(), warning);
+    }
+    for problem in &report.problems {
+        eprintln!("{} {}", "error:".red(), problem);
+    }
+
+    if !report.is_ok() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+// Create, list or delete tags. A tag is lightweight (a ref under
+// `refs/tags`) unless a message or `-a` asks for an annotated tag object.
+// With `-l` the names are patterns that restrict the listing.
+pub fn tag(annotate: bool, message: Option<String>, delete: bool, list: bool, force: bool, points_at: Option<String>, names: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let store = ObjectStore::open()?;
+    let refs = Refs::open();
+
+    if delete {
+        for name in &names {
+            let oid = delete_tag(&refs, name)?;
+            println!("Deleted tag '{}' (was {})", name, &oid.to_hex()[..7]);
+        }
+        return Ok(());
+    }
+
+    if list || names.is_empty() || points_at.is_some() {
+        let points_at = points_at.map(|name| resolve_revision(&store, &refs, &name)).transpose()?;
+        for name in list_tags(&store, &refs, &names, points_at)? {
+            println!("{}", name);
+        }
+        return Ok(());
+    }
+
+    let (name, target) = match names.as_slice() {
+        [name] => (name, "HEAD"),
+        [name, target] => (name, target.as_str()),
+        _ => return Err(Box::new(Error::new(io::ErrorKind::InvalidInput, "too many arguments"))),
+    };
+    let object = resolve_revision(&store, &refs, target)?;
+    let message = if annotate { Some(message.unwrap_or_default()) } else { message };
+    create_tag(&store, &refs, name, object, message, force)?;
+
+    Ok(())
+}
+
+
+// Delete loose objects that nothing reachable refers to and that were last
+// written before `expire`, along with stale temporary files. The grace
+// period protects objects another process is still about to reference.
+pub fn prune(expire: String, dry_run: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let store = ObjectStore::open()?;
+    let Some(expire) = parse_expiry(&expire, chrono::Local::now().timestamp())? else {
+        return Ok(());
+    };
+
+    let roots = repository_roots(&Refs::open())?;
+    let pruned = prune_unreachable(&store, &roots, expire, dry_run)?;
+    if dry_run {
+        for oid in &pruned {
+            let kind = store.read_loose(oid).map(|(kind, _)| kind.as_str()).unwrap_or("unknown");
+            println!("{} {}", oid, kind);
+        }
+    }
+
+    // Temporary files left behind by interrupted object and pack writes.
+    for dir in [store.objects_dir().clone(), store.pack_dir()] {
+        if !dir.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let is_temp = entry.file_name().to_string_lossy().starts_with("tmp_");
+            if is_temp && entry.file_type()?.is_file() && is_expired(entry.metadata()?.modified()?, expire) {
+                if dry_run {
+                    println!("Removing stale temporary file {}", entry.path().display());
+                } else {
+                    fs::remove_file(entry.path())?;
+                }
+            }
+        }
+    }
+
+    if !dry_run {
+        println!("Pruned {} unreachable objects", pruned.len());
+    }
+    Ok(())
+}
+
+
+// Copy the objects, refs and HEAD of a local git repository into this one.
+// Packs are copied as they are and loose objects are re-hashed on the way
+// in, so every object keeps its id.
+pub fn import_git(path: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+    let git_dir = if path.join(".git").is_dir() { path.join(".git") } else { path };
+    if !warp_dir().is_dir() {
+        return Err(Box::new(Error::new(io::ErrorKind::NotFound, "not a warp repository (run `warp init` first)")));
+    }
+
+    let stats = import_repository(&ObjectStore::open()?, &Refs::open(), &git_dir)?;
+    println!("Imported {} packs, {} loose objects and {} refs from {}", stats.packs, stats.loose, stats.refs, git_dir.display());
+    Ok(())
+}
+
+// Write the history of the given refs (all refs by default) to stdout as a
+// git fast-import stream.
+pub fn fast_export(refs: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let store = ObjectStore::open()?;
+    let selected = select_refs(&Refs::open(), &refs)?;
+    FastExporter::new(&store, io::BufWriter::new(io::stdout().lock())).export(&selected)?;
+    Ok(())
+}
+
+// Read a git fast-import stream from stdin into this repository.
+pub fn fast_import() -> Result<(), Box<dyn std::error::Error>> {
+    let store = ObjectStore::open()?;
+    let refs = Refs::open();
+    let stats = FastImporter::new(&store, &refs).import(io::stdin().lock())?;
+    eprintln!("Imported {} blobs, {} commits and {} tags, updated {} refs", stats.blobs, stats.commits, stats.tags, stats.refs);
+    Ok(())
+}
+
+
+// Write a bundle of the given revisions for carrying to another machine.
+pub fn bundle_create(file: PathBuf, revisions: Vec<String>, all: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let store = ObjectStore::open()?;
+    let bundle = bundle::create(&store, &Refs::open(), &file, &revisions, all)?;
+    println!("Created {} with {} refs and {} prerequisites", file.display(), bundle.refs.len(), bundle.prerequisites.len());
+    Ok(())
+}
+
+// Print what a bundle provides and requires, and fail if it is corrupt or
+// this repository lacks one of its prerequisites.
+pub fn bundle_verify(file: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+    let store = ObjectStore::open()?;
+    let (bundle, _) = open_bundle(&store, &file)?;
+
+    println!("The bundle contains {} refs:", bundle.refs.len());
+    for (oid, name) in &bundle.refs {
+        println!("{} {}", oid, name);
+    }
+    if bundle.prerequisites.is_empty() {
+        println!("The bundle records a complete history.");
+    } else {
+        println!("The bundle requires {} refs:", bundle.prerequisites.len());
+        for (oid, comment) in &bundle.prerequisites {
+            println!("{} {}", oid, comment);
+        }
+    }
+    println!("{} is okay", file.display());
+    Ok(())
+}
+
+// Unpack the objects of a bundle and print its refs. Updating refs is left
+// to the caller.
+pub fn bundle_unbundle(file: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+    let store = ObjectStore::open()?;
+    let (bundle, pack) = open_bundle(&store, &file)?;
+    unpack_objects(&pack, &file, &store)?;
+
+    for (oid, name) in &bundle.refs {
+        println!("{} {}", oid, name);
+    }
+    Ok(())
+}
+
+// Read a bundle and check that its pack is intact and can be applied to
+// `store`.
+fn open_bundle(store: &ObjectStore, file: &Path) -> Result<(Bundle, Vec<u8>), Box<dyn std::error::Error>> {
+    let (bundle, pack) = bundle::read(file)?;
+    if bundle.format != store.format() {
+        return Err(Box::new(Error::new(io::ErrorKind::Unsupported, format!(
+            "{} is a {} bundle, but this is a {} repository", file.display(), bundle.format, store.format()
+        ))));
+    }
+
+    let missing = bundle.missing_prerequisites(store);
+    if !missing.is_empty() {
+        let list: Vec<String> = missing.iter().map(ObjectId::to_hex).collect();
+        return Err(Box::new(Error::new(io::ErrorKind::NotFound, format!("repository lacks these prerequisite commits:\n{}", list.join("\n")))));
+    }
+
+    let width = store.format().raw_len();
+    if pack.len() < width || store.format().digest(&pack[..pack.len() - width]).as_bytes() != &pack[pack.len() - width..] {
+        return Err(Box::new(Error::new(io::ErrorKind::InvalidData, format!("{}: pack checksum mismatch", file.display()))));
+    }
+    Ok((bundle, pack))
+}
+
+// List the index entries, with `stage` as `<mode> <object> <stage>\t<path>`.
+pub fn ls_files(stage: bool, unmerged: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(index) = read_index()? else {
+        return Ok(());
+    };
+
+    for entry in index.entries.iter().filter(|entry| !unmerged || entry.stage() != 0) {
+        if stage || unmerged {
+            println!("{:06o} {} {}\t{}", entry.mode, entry.sha, entry.stage(), entry.path);
+        } else {
+            println!("{}", entry.path);
+        }
+    }
+    Ok(())
+}
+
+// Write index entries (all of them, or those for `paths`) to the working
+// tree. Chunked blobs are reassembled and LFS pointers are expanded from
+// the local LFS store once their content is verified; a pointer whose
+// content has not been fetched is written as is. Existing files are left
+// alone unless `force` is set. Paths that would escape the working tree
+// stop the checkout.
+pub fn checkout_index(all: bool, force: bool, paths: Vec<PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
+    let store = ObjectStore::open()?;
+    let lfs = LfsStore::open();
+    let Some(index) = read_index()? else {
+        return Err(Box::new(Error::new(io::ErrorKind::NotFound, "there is no index to check out")));
+    };
+
+    let wanted: Vec<String> = paths.iter().map(|path| path.to_string_lossy().trim_start_matches("./").to_string()).collect();
+    for path in &wanted {
+        if !all && !index.entries.iter().any(|entry| entry.path == *path) {
+            return Err(Box::new(Error::new(io::ErrorKind::NotFound, format!("{} is not in the index", path))));
+        }
+    }
+
+    let (mut missing, mut unmerged) = (0, Vec::new());
+    for entry in index.entries.iter().filter(|entry| all || wanted.contains(&entry.path)) {
+        if entry.stage() != 0 {
+            if !unmerged.contains(&entry.path.as_str()) {
+                unmerged.push(&entry.path);
+            }
+            continue;
+        }
+        let target = checkout_path(Path::new(""), &entry.path)?;
+        if !force && fs::symlink_metadata(&target).is_ok() {
+            eprintln!("warning: {} already exists, not overwriting", entry.path);
+            continue;
+        }
+        if let Some(parent) = target.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        if fs::symlink_metadata(&target).is_ok() {
+            fs::remove_file(&target)?;
+        }
+
+        // A gitlink names a commit of another repository, which is not in
+        // this store, so only blobs and symlinks are read.
+        match entry.mode & 0o170000 {
+            0o120000 => {
+                let (_, content) = store.read(&entry.sha)?;
+                std::os::unix::fs::symlink(String::from_utf8_lossy(&content).as_ref(), &target)?
+            },
+            0o160000 => fs::create_dir_all(&target)?,
+            _ => {
+                let (_, content) = store.read(&entry.sha)?;
+                if let Some(manifest) = Manifest::detect(&content).map_err(|e| Error::new(e.kind(), format!("{}: {}", entry.path, e)))? {
+                    expand(&store, &manifest, &mut io::BufWriter::new(fs::File::create(&target)?))?;
+                } else if let Some(pointer) = Pointer::parse(&content).filter(|pointer| lfs.contains(pointer)) {
+                    lfs.materialize(&pointer, &target).map_err(|e| Error::new(e.kind(), format!("{}: {}", entry.path, e)))?;
+                } else {
+                    missing += usize::from(Pointer::parse(&content).is_some());
+                    fs::write(&target, &content)?;
+                }
+                let mode = if entry.mode & 0o111 != 0 { 0o755 } else { 0o644 };
+                fs::set_permissions(&target, fs::Permissions::from_mode(mode))?;
+            },
+        }
+    }
+
+    for path in unmerged {
+        eprintln!("warning: {} is unmerged, not checked out", path);
+    }
+    if missing > 0 {
+        eprintln!("warning: {} large files are not in the LFS store and were left as pointers (see `warp lfs fetch`)", missing);
+    }
+    Ok(())
+}
+
+// Copy the large file contents referenced by reachable pointers from the
+// repository at `remote` into this one.
+pub fn lfs_fetch(remote: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+    let (local, remote) = (LfsStore::open(), LfsStore::at_repository(&remote)?);
+    let pointers = reachable_pointers(&ObjectStore::open()?, &Refs::open())?;
+
+    let (mut copied, mut unavailable) = (0, 0);
+    for pointer in pointers.iter().filter(|pointer| !local.contains(pointer)) {
+        if remote.contains(pointer) {
+            local.copy_from(&remote, pointer)?;
+            copied += 1;
+        } else {
+            eprintln!("warning: {} is not available in the remote store", pointer.oid);
+            unavailable += 1;
+        }
+    }
+    println!("Fetched {} of {} large files", copied, copied + unavailable);
+    Ok(())
+}
+
+// Copy the large file contents referenced by reachable pointers to the
+// repository at `remote`, skipping those it already has.
+pub fn lfs_push(remote: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+    let (local, remote) = (LfsStore::open(), LfsStore::at_repository(&remote)?);
+    let pointers = reachable_pointers(&ObjectStore::open()?, &Refs::open())?;
+
+    let mut copied = 0;
+    for pointer in pointers.iter().filter(|pointer| !remote.contains(pointer)) {
+        if !local.contains(pointer) {
+            return Err(Box::new(Error::new(io::ErrorKind::NotFound, format!("{} is missing from the local LFS store", pointer.oid))));
+        }
+        remote.copy_from(&local, pointer)?;
+        copied += 1;
+    }
+    println!("Pushed {} large files", copied);
+    Ok(())
+}
@@ -1,98 +1,160 @@
-use std::{env, fs, io::Write};
-
-use chrono::{DateTime, Local};
-use flate2::{write::ZlibEncoder, Compression};
-use sha1::{Digest, Sha1};
-
-
-const AUTHOR: &'static str = "dummy author";
-const COMMITTER: &'static str = "dummy author";
-const AUTHOR_EMAIL: &'static str = "dummy@email.com";
-const COMMITTER_EMAIL: &'static str = "dummy@email.com";
-
-pub struct Commit<'a> {
-    tree: String,
-    parent_tree: Option<String>,
-    author_name: &'a str,
-    committer_name: &'a str,
-    author_email: &'a str,
-    commit_email: &'a str,
-    commit_date: DateTime<Local>,
-    commit_message: String
+use std::io;
+
+use chrono::Local;
+
+use crate::object::{header_text, invalid_object, parse_headers, write_headers};
+use crate::object_id::ObjectId;
+use crate::object_store::{ObjectKind, ObjectStore};
+
+
+const AUTHOR: &str = "dummy author";
+const COMMITTER: &str = "dummy author";
+const AUTHOR_EMAIL: &str = "dummy@email.com";
+const COMMITTER_EMAIL: &str = "dummy@email.com";
+
+// An identity line such as `author Name <email> 1700000000 +0100`. Name
+// and email are raw bytes, since commits with an `encoding` header need not
+// use UTF-8. The line as read is kept too, so that odd spacing or a padded
+// timestamp survive parsing and serializing byte-for-byte.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signature {
+    pub name: Vec<u8>,
+    pub email: Vec<u8>,
+    pub timestamp: i64,
+    pub timezone: String,
+    raw: Option<Vec<u8>>
 }
 
-impl<'a> Commit<'a> {
-    pub fn new(tree: String, parents: Option<String>, message: String) -> Self {        
-        Commit {
-            tree,
-            parent_tree: parents,
-            author_name: AUTHOR,
-            committer_name: COMMITTER,
-            author_email: AUTHOR_EMAIL,
-            commit_email: COMMITTER_EMAIL,
-            commit_date: Local::now(),
-            commit_message: message,
+impl Signature {
+    pub fn new(name: &str, email: &str) -> Self {
+        let now = Local::now();
+        Self {
+            name: name.as_bytes().to_vec(),
+            email: email.as_bytes().to_vec(),
+            timestamp: now.timestamp(),
+            timezone: now.format("%z").to_string(),
+            raw: None,
         }
     }
 
-    fn hash_content(&self) -> Vec<u8> {
-        let mut buf = Vec::new();
-        let tree_object_hash = format!("tree {}\n", self.tree);
-        let parent_object_hash = if let Some(parent) = &self.parent_tree {
-            format!("parent {}\n", parent)
-        } else { String::new() };
-        let author = format!("author {} <{}> {} {}\n", self.author_name, self.author_email, self.commit_date.timestamp(), self.commit_date.offset());
-        let committer = format!("committer {} <{}> {} {}\n", self.committer_name, self.commit_email, self.commit_date.timestamp(), self.commit_date.offset());
-
-        buf.extend_from_slice(tree_object_hash.as_bytes());
-        buf.extend_from_slice(parent_object_hash.as_bytes());
-        buf.extend_from_slice(author.as_bytes());
-        buf.extend_from_slice(committer.as_bytes());
-        buf.extend_from_slice(b"\n");
-        buf.extend_from_slice(self.commit_message.as_bytes());
-        buf.extend_from_slice(b"\n");
-
-        buf
-    }
+    pub fn parse(value: &[u8]) -> io::Result<Self> {
+        let open = value.iter().position(|&b| b == b'<').ok_or_else(|| invalid_object("signature is missing '<'"))?;
+        let close = value.iter().rposition(|&b| b == b'>').ok_or_else(|| invalid_object("signature is missing '>'"))?;
+        if close < open {
+            return Err(invalid_object("malformed signature"));
+        }
 
-    fn hash_commit_object(&self) -> Vec<u8> {
-        let mut hashable = self.hash_content();
-        let commit_header = format!("commit {}", hashable.len());
+        let name = value[..open].trim_ascii_end().to_vec();
+        let email = value[open + 1..close].to_vec();
+        let when = std::str::from_utf8(&value[close + 1..]).map_err(|_| invalid_object("malformed signature timestamp"))?;
+        let (timestamp, timezone) = when.trim_start().split_once(' ')
+            .ok_or_else(|| invalid_object("signature is missing a timezone"))?;
+        let timestamp = timestamp.parse().map_err(|_| invalid_object("malformed signature timestamp"))?;
 
-        [commit_header.as_bytes(), &hashable].concat()
+        Ok(Signature { name, email, timestamp, timezone: timezone.trim_start().to_string(), raw: Some(value.to_vec()) })
     }
 
-    pub fn hash(&self) -> Result<String, Box<dyn std::error::Error>> {
-        let mut hasher = Sha1::new();
-        let hash_object = self.hash_commit_object();
+    // The timezone as a signed number of minutes east of UTC.
+    pub fn offset_minutes(&self) -> i32 {
+        let (sign, digits) = match self.timezone.split_at_checked(1) {
+            Some(("-", digits)) => (-1, digits),
+            Some((_, digits)) => (1, digits),
+            None => return 0,
+        };
+        let value: i32 = digits.parse().unwrap_or(0);
+        sign * ((value / 100) * 60 + value % 100)
+    }
 
-        hasher.update(hash_object);
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // Written back as read, unless a field has been changed since.
+        if let Some(raw) = &self.raw {
+            let unchanged = Signature::parse(raw).is_ok_and(|read| {
+                read.name == self.name && read.email == self.email && read.timestamp == self.timestamp && read.timezone == self.timezone
+            });
+            if unchanged {
+                return raw.clone();
+            }
+        }
+
+        let mut bytes = self.name.clone();
+        bytes.extend(b" <");
+        bytes.extend(&self.email);
+        bytes.extend(format!("> {} {}", self.timestamp, self.timezone).as_bytes());
+        bytes
+    }
+}
 
-        let result = hasher.finalize();
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Commit {
+    pub tree: ObjectId,
+    pub parents: Vec<ObjectId>,
+    pub author: Signature,
+    pub committer: Signature,
+    // Headers after `committer` (encoding, gpgsig, mergetag, ...) in their
+    // original order. Multi-line values are stored with `\n` separators.
+    pub extra_headers: Vec<(String, Vec<u8>)>,
+    pub message: Vec<u8>,
+    // Whether a blank line ends the headers. Only a commit without a
+    // message can lack it.
+    pub separator: bool
+}
 
-        Ok(hex::encode(result))
+impl Commit {
+    pub fn new(tree: ObjectId, parents: Vec<ObjectId>, message: String) -> Self {
+        Commit {
+            tree,
+            parents,
+            author: Signature::new(AUTHOR, AUTHOR_EMAIL),
+            committer: Signature::new(COMMITTER, COMMITTER_EMAIL),
+            extra_headers: Vec::new(),
+            message: format!("{}\n", message).into_bytes(),
+            separator: true,
+        }
     }
 
-    pub fn compress_to_object(&self) {
-        let hash = self.hash_commit_object();
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let (headers, message) = parse_headers(bytes)?;
+        let separator = message.is_some();
+        let mut headers = headers.into_iter().peekable();
+
+        let tree = match headers.next() {
+            Some((key, value)) if key == "tree" => header_text(&key, &value)?.parse()?,
+            _ => return Err(invalid_object("commit does not start with a tree")),
+        };
 
-        let mut encoded = ZlibEncoder::new(Vec::new(), Compression::default());
-        encoded.write_all(&hash).expect("Error writing to compressing stream");
-        let compressed_bytes = encoded.finish().expect("Error compressing hash content");
+        let mut parents = Vec::new();
+        while let Some((_, value)) = headers.next_if(|(key, _)| key == "parent") {
+            parents.push(header_text("parent", &value)?.parse()?);
+        }
 
-        let object = self.hash().expect("Error hashing file");
+        let author = match headers.next() {
+            Some((key, value)) if key == "author" => Signature::parse(&value)?,
+            _ => return Err(invalid_object("commit is missing an author")),
+        };
+        let committer = match headers.next() {
+            Some((key, value)) if key == "committer" => Signature::parse(&value)?,
+            _ => return Err(invalid_object("commit is missing a committer")),
+        };
 
-        let mut root = env::current_dir().expect("Unable to get cwd");
-        root.push(".warp");
-        root.push("objects");
-        let (dir_hash, file_hash) = object.split_at(2);
-        crate::auxiliary::push_dir_with_file(root.clone(), dir_hash, file_hash);
+        Ok(Commit { tree, parents, author, committer, extra_headers: headers.collect(), message: message.unwrap_or_default(), separator })
+    }
 
-        root.push(dir_hash);
-        root.push(file_hash);
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut headers = vec![("tree".to_string(), self.tree.to_hex().into_bytes())];
+        headers.extend(self.parents.iter().map(|parent| ("parent".to_string(), parent.to_hex().into_bytes())));
+        headers.push(("author".to_string(), self.author.to_bytes()));
+        headers.push(("committer".to_string(), self.committer.to_bytes()));
+        headers.extend(self.extra_headers.iter().cloned());
 
-        let mut compressed_file = fs::File::create(root).expect("Unable to open file");
-        compressed_file.write(&compressed_bytes).expect("Error writing to file");
+        write_headers(&headers, &self.message, self.separator)
     }
 
-}
\ No newline at end of file
+    pub fn hash(&self) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(ObjectStore::open()?.hash(ObjectKind::Commit, &self.to_bytes()).to_hex())
+    }
+
+    pub fn compress_to_object(&self) -> io::Result<ObjectId> {
+        ObjectStore::open()?.write(ObjectKind::Commit, &self.to_bytes())
+    }
+
+}
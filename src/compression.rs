@@ -0,0 +1,173 @@
+use core::fmt;
+use std::{io::{self, BufRead, Read, Write}, str::FromStr};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder};
+
+// Every zstd frame starts with these bytes; zlib streams never do, so the
+// codec of a loose object can be told from its first bytes.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+pub const DEFAULT_ZLIB_LEVEL: u32 = 6;
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+// How new loose objects are compressed. zlib is what git reads; zstd is
+// recorded as `extensions.compression` so that builds without it refuse the
+// repository instead of failing on the first object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Zlib(u32),
+    Zstd(i32)
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Codec::Zlib(DEFAULT_ZLIB_LEVEL)
+    }
+}
+
+impl Codec {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::Zlib(_) => "zlib",
+            Codec::Zstd(_) => "zstd",
+        }
+    }
+
+    // Apply a `core.compression` style level: 0-9 for zlib, 1-22 for zstd,
+    // and -1 for the codec's default.
+    pub fn with_level(self, level: i32) -> io::Result<Self> {
+        match (self, level) {
+            (Codec::Zlib(_), -1) => Ok(Codec::Zlib(DEFAULT_ZLIB_LEVEL)),
+            (Codec::Zlib(_), 0..=9) => Ok(Codec::Zlib(level as u32)),
+            (Codec::Zstd(_), -1) => Ok(Codec::Zstd(DEFAULT_ZSTD_LEVEL)),
+            (Codec::Zstd(_), 1..=22) => Ok(Codec::Zstd(level)),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid {} compression level {}", self.name(), level))),
+        }
+    }
+
+    pub fn encoder<W: Write>(&self, out: W) -> io::Result<Encoder<W>> {
+        match *self {
+            Codec::Zlib(level) => Ok(Encoder::Zlib(ZlibEncoder::new(out, flate2::Compression::new(level)))),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(level) => Ok(Encoder::Zstd(zstd::stream::write::Encoder::new(out, level)?)),
+            #[cfg(not(feature = "zstd"))]
+            Codec::Zstd(_) => Err(unsupported_zstd()),
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Codec {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "zlib" => Ok(Codec::Zlib(DEFAULT_ZLIB_LEVEL)),
+            "zstd" if cfg!(feature = "zstd") => Ok(Codec::Zstd(DEFAULT_ZSTD_LEVEL)),
+            "zstd" => Err(unsupported_zstd()),
+            other => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown compression codec '{}'", other))),
+        }
+    }
+}
+
+fn unsupported_zstd() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "this build was compiled without zstd support")
+}
+
+// A compressing writer for either codec.
+pub enum Encoder<W: Write> {
+    Zlib(ZlibEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, W>)
+}
+
+impl<W: Write> Encoder<W> {
+    // Flush the end of the stream and hand back the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::Zlib(encoder) => encoder.finish(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Zlib(encoder) => encoder.write(buf),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Zlib(encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+// Decompress a loose object written with either codec, whatever the
+// repository is configured to write now.
+pub fn decompress(compressed: &[u8]) -> io::Result<Vec<u8>> {
+    if compressed.starts_with(&ZSTD_MAGIC) {
+        #[cfg(feature = "zstd")]
+        return zstd::stream::decode_all(compressed);
+        #[cfg(not(feature = "zstd"))]
+        return Err(unsupported_zstd());
+    }
+
+    let mut raw = Vec::new();
+    ZlibDecoder::new(compressed).read_to_end(&mut raw)?;
+    Ok(raw)
+}
+
+// A streaming decompressor for a loose object written with either codec,
+// for callers that only need the start of the object.
+pub fn decoder<'a, R: BufRead + 'a>(mut compressed: R) -> io::Result<Box<dyn Read + 'a>> {
+    if compressed.fill_buf()?.starts_with(&ZSTD_MAGIC) {
+        #[cfg(feature = "zstd")]
+        return Ok(Box::new(zstd::stream::read::Decoder::with_buffer(compressed)?));
+        #[cfg(not(feature = "zstd"))]
+        return Err(unsupported_zstd());
+    }
+
+    Ok(Box::new(flate2::bufread::ZlibDecoder::new(compressed)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codecs_round_trip() {
+        let data = b"blob 12\0hello world\n".repeat(100);
+        let mut codecs = vec![Codec::Zlib(0), Codec::Zlib(9)];
+        if cfg!(feature = "zstd") {
+            codecs.push(Codec::Zstd(DEFAULT_ZSTD_LEVEL));
+        }
+
+        for codec in codecs {
+            let mut encoder = codec.encoder(Vec::new()).unwrap();
+            encoder.write_all(&data).unwrap();
+            let compressed = encoder.finish().unwrap();
+            assert_eq!(decompress(&compressed).unwrap(), data, "{:?}", codec);
+
+            let mut streamed = Vec::new();
+            decoder(compressed.as_slice()).unwrap().read_to_end(&mut streamed).unwrap();
+            assert_eq!(streamed, data, "{:?}", codec);
+        }
+
+        assert_eq!(Codec::default().with_level(-1).unwrap(), Codec::Zlib(DEFAULT_ZLIB_LEVEL));
+        assert!(Codec::Zlib(1).with_level(10).is_err());
+        assert!(Codec::Zstd(1).with_level(0).is_err());
+    }
+}
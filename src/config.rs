@@ -0,0 +1,191 @@
+use std::{fs, io, path::PathBuf};
+
+use crate::auxiliary::warp_dir;
+use crate::compression::Codec;
+use crate::object_id::ObjectFormat;
+
+// Repository format extensions this build understands. A repository with
+// `core.repositoryformatversion = 1` and any other extension is refused.
+const KNOWN_EXTENSIONS: &[&str] = &["objectformat", "compression"];
+
+// The `.warp/config` file: git-style `[section]` or `[section "sub"]`
+// headers followed by `key = value` lines. Keys are addressed as
+// `section.key` or `section.sub.key`, case-insensitively outside `sub`.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    path: PathBuf,
+    sections: Vec<(String, Vec<(String, String)>)>
+}
+
+impl Config {
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        let mut sections: Vec<(String, Vec<(String, String)>)> = Vec::new();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                let name = match header.split_once(' ') {
+                    Some((section, sub)) => format!("{}.{}", section.to_ascii_lowercase(), sub.trim().trim_matches('"')),
+                    None => header.to_ascii_lowercase(),
+                };
+                sections.push((name, Vec::new()));
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').unwrap_or((line, "true"));
+            let Some((_, entries)) = sections.last_mut() else {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}: '{}' is outside of a section", path.display(), line)));
+            };
+            entries.push((key.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+
+        Ok(Self { path, sections })
+    }
+
+    pub fn open() -> io::Result<Self> {
+        Self::load(warp_dir().join("config"))
+    }
+
+    fn split_key(key: &str) -> (String, String) {
+        let (section, name) = key.rsplit_once('.').unwrap_or(("", key));
+        let section = match section.split_once('.') {
+            Some((section, sub)) => format!("{}.{}", section.to_ascii_lowercase(), sub),
+            None => section.to_ascii_lowercase(),
+        };
+        (section, name.to_ascii_lowercase())
+    }
+
+    // The last value set for `key`, as git does for repeated keys.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let (section, name) = Self::split_key(key);
+        self.sections.iter()
+            .filter(|(candidate, _)| *candidate == section)
+            .flat_map(|(_, entries)| entries.iter())
+            .filter(|(candidate, _)| *candidate == name)
+            .map(|(_, value)| value.as_str())
+            .next_back()
+    }
+
+    // A size in bytes, optionally scaled by a `k`, `m` or `g` suffix.
+    pub fn get_size(&self, key: &str) -> io::Result<Option<u64>> {
+        let Some(value) = self.get(key) else {
+            return Ok(None);
+        };
+        let lower = value.to_ascii_lowercase();
+        let (digits, scale) = match lower.as_bytes().last() {
+            Some(b'k') => (&lower[..lower.len() - 1], 1 << 10),
+            Some(b'm') => (&lower[..lower.len() - 1], 1 << 20),
+            Some(b'g') => (&lower[..lower.len() - 1], 1 << 30),
+            _ => (lower.as_str(), 1),
+        };
+        digits.parse::<u64>().ok().and_then(|size| size.checked_mul(scale)).map(Some)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{} '{}' is not a size", key, value)))
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        let (section, name) = Self::split_key(key);
+        let position = match self.sections.iter().position(|(candidate, _)| *candidate == section) {
+            Some(position) => position,
+            None => {
+                self.sections.push((section, Vec::new()));
+                self.sections.len() - 1
+            }
+        };
+
+        let entries = &mut self.sections[position].1;
+        match entries.iter_mut().find(|(candidate, _)| *candidate == name) {
+            Some(entry) => entry.1 = value.to_string(),
+            None => entries.push((name, value.to_string())),
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut content = String::new();
+        for (section, entries) in &self.sections {
+            match section.split_once('.') {
+                Some((section, sub)) => content.push_str(&format!("[{} \"{}\"]\n", section, sub)),
+                None => content.push_str(&format!("[{}]\n", section)),
+            }
+            for (key, value) in entries {
+                content.push_str(&format!("\t{} = {}\n", key, value));
+            }
+        }
+        fs::write(&self.path, content)
+    }
+
+    // Refuse repositories that rely on extensions this build does not know.
+    pub fn check_format(&self) -> io::Result<()> {
+        let version: u32 = self.get("core.repositoryformatversion").unwrap_or("0").parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "core.repositoryformatversion is not a number"))?;
+        if version > 1 {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, format!("unsupported repository format version {}", version)));
+        }
+        if version == 1 {
+            let extensions = self.sections.iter().filter(|(section, _)| section == "extensions").flat_map(|(_, entries)| entries.iter());
+            for (extension, _) in extensions {
+                if !KNOWN_EXTENSIONS.contains(&extension.as_str()) {
+                    return Err(io::Error::new(io::ErrorKind::Unsupported, format!("repository requires unknown extension '{}'", extension)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn object_format(&self) -> io::Result<ObjectFormat> {
+        self.check_format()?;
+        match self.get("extensions.objectformat") {
+            Some(format) => format.parse(),
+            None => Ok(ObjectFormat::Sha1),
+        }
+    }
+
+    // The codec for new loose objects: `extensions.compression` picks zlib
+    // (the default) or zstd, and `core.looseCompression`, falling back to
+    // `core.compression`, sets the level.
+    pub fn compression(&self) -> io::Result<Codec> {
+        self.check_format()?;
+        let codec: Codec = self.get("extensions.compression").unwrap_or("zlib").parse()?;
+        match self.get("core.loosecompression").or(self.get("core.compression")) {
+            Some(level) => {
+                let level = level.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("compression level '{}' is not a number", level)))?;
+                codec.with_level(level)
+            },
+            None => Ok(codec),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_round_trip() {
+        let path = std::env::temp_dir().join(format!("warp-config-{}", std::process::id()));
+        let mut config = Config::load(path.clone()).unwrap();
+        config.set("core.repositoryformatversion", "1");
+        config.set("extensions.objectFormat", "sha256");
+        config.set("remote.Origin.url", "/srv/repo");
+        config.set("lfs.threshold", "100M");
+        config.save().unwrap();
+
+        let config = Config::load(path.clone()).unwrap();
+        assert_eq!(config.get("Core.RepositoryFormatVersion"), Some("1"));
+        assert_eq!(config.get("remote.Origin.url"), Some("/srv/repo"));
+        assert_eq!(config.get_size("lfs.threshold").unwrap(), Some(100 << 20));
+        assert!(config.get_size("remote.Origin.url").is_err());
+        assert_eq!(config.object_format().unwrap(), ObjectFormat::Sha256);
+        assert_eq!(config.compression().unwrap(), Codec::default());
+
+        fs::remove_file(path).unwrap();
+    }
+}
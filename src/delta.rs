@@ -0,0 +1,220 @@
+use std::{collections::HashMap, io};
+
+// Base blocks of this many bytes are indexed when looking for copies.
+const BLOCK_SIZE: usize = 16;
+// Limit how many base offsets are remembered for one block, so that highly
+// repetitive content does not make matching quadratic.
+const MAX_BUCKET: usize = 64;
+// Largest copy a single instruction can describe (three size bytes).
+const MAX_COPY: usize = 0xff_ffff;
+// The target size in a delta is only trusted this far when reserving
+// memory, since it comes straight from the pack.
+const MAX_PREALLOCATION: usize = 1 << 20;
+
+fn invalid_delta(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid delta: {}", reason))
+}
+
+// Read one of the little-endian base-128 sizes at the start of a delta.
+fn read_size(delta: &[u8], pos: &mut usize) -> io::Result<usize> {
+    let mut size = 0usize;
+    let mut shift = 0;
+    loop {
+        if shift > usize::BITS - 7 {
+            return Err(invalid_delta("size is too large"));
+        }
+        let byte = *delta.get(*pos).ok_or_else(|| invalid_delta("truncated size"))?;
+        *pos += 1;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(size);
+        }
+    }
+}
+
+// The size of the object a delta rebuilds, which only needs the first bytes
+// of the delta.
+pub fn target_size(delta: &[u8]) -> io::Result<usize> {
+    let mut pos = 0;
+    read_size(delta, &mut pos)?;
+    read_size(delta, &mut pos)
+}
+
+// Rebuild an object from its base and a git delta: a source size, a target
+// size, then a stream of copy (high bit set) and insert instructions.
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
+    let mut pos = 0;
+    let source_size = read_size(delta, &mut pos)?;
+    let target_size = read_size(delta, &mut pos)?;
+    if source_size != base.len() {
+        return Err(invalid_delta("base size does not match"));
+    }
+
+    let mut target = Vec::with_capacity(target_size.min(MAX_PREALLOCATION));
+    while pos < delta.len() {
+        let instruction = delta[pos];
+        pos += 1;
+
+        if instruction & 0x80 != 0 {
+            let mut offset = 0usize;
+            let mut size = 0usize;
+            for bit in 0..4 {
+                if instruction & (1 << bit) != 0 {
+                    let byte = *delta.get(pos).ok_or_else(|| invalid_delta("truncated copy offset"))?;
+                    offset |= (byte as usize) << (8 * bit);
+                    pos += 1;
+                }
+            }
+            for bit in 0..3 {
+                if instruction & (0x10 << bit) != 0 {
+                    let byte = *delta.get(pos).ok_or_else(|| invalid_delta("truncated copy size"))?;
+                    size |= (byte as usize) << (8 * bit);
+                    pos += 1;
+                }
+            }
+            if size == 0 {
+                size = 0x10000;
+            }
+            let chunk = base.get(offset..offset + size).ok_or_else(|| invalid_delta("copy outside of base"))?;
+            target.extend_from_slice(chunk);
+        } else if instruction != 0 {
+            let chunk = delta.get(pos..pos + instruction as usize).ok_or_else(|| invalid_delta("truncated insert"))?;
+            target.extend_from_slice(chunk);
+            pos += instruction as usize;
+        } else {
+            return Err(invalid_delta("reserved instruction 0"));
+        }
+    }
+
+    if target.len() != target_size {
+        return Err(invalid_delta("result size does not match"));
+    }
+    Ok(target)
+}
+
+fn write_size(out: &mut Vec<u8>, mut size: usize) {
+    loop {
+        let byte = (size & 0x7f) as u8;
+        size >>= 7;
+        if size == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_insert(out: &mut Vec<u8>, data: &[u8]) {
+    for chunk in data.chunks(0x7f) {
+        out.push(chunk.len() as u8);
+        out.extend_from_slice(chunk);
+    }
+}
+
+fn write_copy(out: &mut Vec<u8>, mut offset: usize, mut size: usize) {
+    while size > 0 {
+        let chunk = size.min(MAX_COPY);
+        let mut instruction = 0x80u8;
+        let mut args = Vec::with_capacity(7);
+        for bit in 0..4 {
+            let byte = ((offset >> (8 * bit)) & 0xff) as u8;
+            if byte != 0 {
+                instruction |= 1 << bit;
+                args.push(byte);
+            }
+        }
+        for bit in 0..3 {
+            let byte = ((chunk >> (8 * bit)) & 0xff) as u8;
+            if byte != 0 {
+                instruction |= 0x10 << bit;
+                args.push(byte);
+            }
+        }
+        out.push(instruction);
+        out.extend(args);
+
+        offset += chunk;
+        size -= chunk;
+    }
+}
+
+// Describe `target` as copies out of `base` plus literal inserts. Base
+// blocks are indexed at a fixed stride; every match found through the index
+// is extended forwards and backwards as far as the bytes agree.
+pub fn create_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_size(&mut out, base.len());
+    write_size(&mut out, target.len());
+
+    let mut blocks: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for offset in (0..base.len().saturating_sub(BLOCK_SIZE - 1)).step_by(BLOCK_SIZE) {
+        let bucket = blocks.entry(&base[offset..offset + BLOCK_SIZE]).or_default();
+        if bucket.len() < MAX_BUCKET {
+            bucket.push(offset);
+        }
+    }
+
+    let mut insert_start = 0;
+    let mut pos = 0;
+    while pos + BLOCK_SIZE <= target.len() {
+        let mut best: Option<(usize, usize, usize)> = None;
+        if let Some(candidates) = blocks.get(&target[pos..pos + BLOCK_SIZE]) {
+            for &candidate in candidates {
+                let mut length = BLOCK_SIZE;
+                while candidate + length < base.len() && pos + length < target.len() && base[candidate + length] == target[pos + length] {
+                    length += 1;
+                }
+                let mut back = 0;
+                while back < pos - insert_start && back < candidate && base[candidate - back - 1] == target[pos - back - 1] {
+                    back += 1;
+                }
+                if best.is_none_or(|(_, _, best_length)| length + back > best_length) {
+                    best = Some((candidate - back, pos - back, length + back));
+                }
+            }
+        }
+
+        match best {
+            Some((base_offset, target_offset, length)) => {
+                write_insert(&mut out, &target[insert_start..target_offset]);
+                write_copy(&mut out, base_offset, length);
+                pos = target_offset + length;
+                insert_start = pos;
+            },
+            None => pos += 1,
+        }
+    }
+    write_insert(&mut out, &target[insert_start..]);
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_delta() {
+        let base = b"hello world";
+        // source 11, target 13, copy 6 bytes from 0, insert "there", copy 2 bytes from 9.
+        let delta = [11, 13, 0x90, 6, 5, b't', b'h', b'e', b'r', b'e', 0x91, 9, 2];
+        assert_eq!(apply_delta(base, &delta).unwrap(), b"hello thereld");
+
+        // A size that never ends is rejected rather than overflowing.
+        assert_eq!(target_size(&[0xff; 16]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_create_delta_round_trip() {
+        let base: Vec<u8> = (0..20_000u32).flat_map(|n| format!("line {}\n", n).into_bytes()).collect();
+        let mut target = base.clone();
+        target.splice(5_000..5_010, b"an edited region".iter().copied());
+        target.extend_from_slice(b"appended tail");
+
+        let delta = create_delta(&base, &target);
+        assert!(delta.len() < 200);
+        assert_eq!(apply_delta(&base, &delta).unwrap(), target);
+        assert_eq!(apply_delta(b"", &create_delta(b"", b"short")).unwrap(), b"short");
+    }
+}
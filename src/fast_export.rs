@@ -0,0 +1,218 @@
+use std::{collections::{HashMap, HashSet}, io::{self, Write}};
+
+use crate::chunking::{expand, Manifest};
+use crate::commit::Commit;
+use crate::object::Object;
+use crate::object_id::ObjectId;
+use crate::object_store::{ObjectKind, ObjectStore};
+use crate::tag::Tag;
+use crate::tree::{read_paths, PathMap};
+
+// Writes the history behind a set of refs as a `git fast-import` stream.
+// Blobs and commits are numbered with marks as they are emitted so later
+// commands can refer back to them.
+pub struct FastExporter<'a, W: Write> {
+    store: &'a ObjectStore,
+    out: W,
+    marks: HashMap<ObjectId, u64>,
+    next_mark: u64
+}
+
+impl<'a, W: Write> FastExporter<'a, W> {
+    pub fn new(store: &'a ObjectStore, out: W) -> Self {
+        Self {
+            store,
+            out,
+            marks: HashMap::new(),
+            next_mark: 1
+        }
+    }
+
+    // Export each `(ref name, target)` in order. Commits reachable from
+    // several refs are written once, under the first ref that reaches them;
+    // a closing `reset` puts every branch at its own tip.
+    pub fn export(&mut self, refs: &[(String, ObjectId)]) -> io::Result<()> {
+        let mut tags = Vec::new();
+        for (name, oid) in refs {
+            let (commit, tag) = self.peel(oid)?;
+            if let Some(tag) = tag {
+                tags.push((name.clone(), tag));
+            }
+            for (oid, commit) in self.unexported_history(commit)? {
+                self.write_commit(name, oid, &commit)?;
+            }
+        }
+
+        for (name, oid) in refs {
+            if let Some(tag) = tags.iter().find(|(tag_name, _)| tag_name == name).map(|(_, tag)| tag.clone()) {
+                self.write_tag(name, &tag)?;
+                continue;
+            }
+            let (commit, _) = self.peel(oid)?;
+            writeln!(self.out, "reset {}\nfrom :{}\n", name, self.marks[&commit])?;
+        }
+
+        writeln!(self.out, "done")?;
+        self.out.flush()
+    }
+
+    // The commit a ref points at, and the annotated tag in between if any.
+    fn peel(&self, oid: &ObjectId) -> io::Result<(ObjectId, Option<Tag>)> {
+        match Object::read(self.store, oid)? {
+            Object::Commit(_) => Ok((*oid, None)),
+            Object::Tag(tag) if tag.kind == ObjectKind::Commit => Ok((tag.object, Some(tag))),
+            other => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is a {}, only commits can be exported", oid, other.kind()))),
+        }
+    }
+
+    // Commits reachable from `tip` that have no mark yet, parents first.
+    fn unexported_history(&self, tip: ObjectId) -> io::Result<Vec<(ObjectId, Commit)>> {
+        let mut ordered = Vec::new();
+        let mut visited = HashSet::new();
+        // (commit, whether its parents have already been pushed)
+        let mut pending = vec![(tip, false)];
+
+        while let Some((oid, expanded)) = pending.pop() {
+            if self.marks.contains_key(&oid) {
+                continue;
+            }
+            if expanded {
+                let Object::Commit(commit) = Object::read(self.store, &oid)? else {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a commit", oid)));
+                };
+                ordered.push((oid, commit));
+                continue;
+            }
+            if !visited.insert(oid) {
+                continue;
+            }
+
+            let Object::Commit(commit) = Object::read(self.store, &oid)? else {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a commit", oid)));
+            };
+            pending.push((oid, true));
+            pending.extend(commit.parents.iter().rev().filter(|parent| !visited.contains(*parent)).map(|parent| (*parent, false)));
+        }
+
+        Ok(ordered)
+    }
+
+    fn mark(&mut self, oid: ObjectId) -> u64 {
+        let mark = self.next_mark;
+        self.next_mark += 1;
+        self.marks.insert(oid, mark);
+        mark
+    }
+
+    // A command whose argument is copied from an object as raw bytes.
+    fn write_line(&mut self, command: &str, value: &[u8]) -> io::Result<()> {
+        write!(self.out, "{} ", command)?;
+        self.out.write_all(value)?;
+        writeln!(self.out)
+    }
+
+    fn write_data(&mut self, data: &[u8]) -> io::Result<()> {
+        writeln!(self.out, "data {}", data.len())?;
+        self.out.write_all(data)?;
+        writeln!(self.out)
+    }
+
+    fn write_commit(&mut self, ref_name: &str, oid: ObjectId, commit: &Commit) -> io::Result<()> {
+        let parent_paths = match commit.parents.first() {
+            Some(parent) => {
+                let Object::Commit(parent) = Object::read(self.store, parent)? else {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("parent {} is not a commit", parent)));
+                };
+                read_paths(self.store, &parent.tree)?
+            },
+            None => PathMap::new(),
+        };
+        let paths = read_paths(self.store, &commit.tree)?;
+
+        // Blobs go out before the commit that first uses them. Chunked
+        // files go out as their whole content.
+        for (mode, blob) in paths.values() {
+            if *mode != 0o160000 && !self.marks.contains_key(blob) {
+                let (_, content) = self.store.read(blob)?;
+                let mark = self.mark(*blob);
+                writeln!(self.out, "blob\nmark :{}", mark)?;
+                match Manifest::detect(&content).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", blob, e)))? {
+                    Some(manifest) => {
+                        writeln!(self.out, "data {}", manifest.size)?;
+                        expand(self.store, &manifest, &mut self.out)?;
+                        writeln!(self.out)?;
+                    },
+                    None => self.write_data(&content)?,
+                }
+            }
+        }
+
+        let mark = self.mark(oid);
+        writeln!(self.out, "commit {}\nmark :{}", ref_name, mark)?;
+        self.write_line("author", &commit.author.to_bytes())?;
+        self.write_line("committer", &commit.committer.to_bytes())?;
+        if let Some((_, encoding)) = commit.extra_headers.iter().find(|(key, _)| key == "encoding") {
+            self.write_line("encoding", encoding)?;
+        }
+        self.write_data(&commit.message)?;
+
+        for (position, parent) in commit.parents.iter().enumerate() {
+            writeln!(self.out, "{} :{}", if position == 0 { "from" } else { "merge" }, self.marks[parent])?;
+        }
+
+        for path in parent_paths.keys().filter(|path| !paths.contains_key(*path)) {
+            writeln!(self.out, "D {}", quote_path(path))?;
+        }
+        for (path, (mode, blob)) in &paths {
+            if parent_paths.get(path) == Some(&(*mode, *blob)) {
+                continue;
+            }
+            match self.marks.get(blob) {
+                Some(blob_mark) if *mode != 0o160000 => writeln!(self.out, "M {:o} :{} {}", normalize_mode(*mode), blob_mark, quote_path(path))?,
+                _ => writeln!(self.out, "M {:o} {} {}", normalize_mode(*mode), blob, quote_path(path))?,
+            }
+        }
+        writeln!(self.out)
+    }
+
+    fn write_tag(&mut self, ref_name: &str, tag: &Tag) -> io::Result<()> {
+        let name = ref_name.strip_prefix("refs/tags/").unwrap_or(ref_name);
+        writeln!(self.out, "tag {}\nfrom :{}", name, self.marks[&tag.object])?;
+        if let Some(tagger) = &tag.tagger {
+            self.write_line("tagger", &tagger.to_bytes())?;
+        }
+        self.write_data(&tag.message)
+    }
+}
+
+// fast-import only knows the modes git writes.
+fn normalize_mode(mode: u32) -> u32 {
+    match mode {
+        0o120000 | 0o160000 => mode,
+        mode if mode & 0o111 != 0 => 0o100755,
+        _ => 0o100644,
+    }
+}
+
+// Paths that could be mistaken for stream syntax, or that are not plain
+// ASCII, are written C-style quoted with octal escapes, as git does.
+pub(crate) fn quote_path(path: &[u8]) -> String {
+    let needs_escape = |byte: u8| byte == b'"' || byte == b'\\' || !(0x20..0x7f).contains(&byte);
+    if !path.iter().any(|&byte| needs_escape(byte)) {
+        return path.iter().map(|&byte| byte as char).collect();
+    }
+
+    let mut quoted = String::from("\"");
+    for &byte in path {
+        match byte {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\t' => quoted.push_str("\\t"),
+            byte if needs_escape(byte) => quoted.push_str(&format!("\\{:03o}", byte)),
+            byte => quoted.push(byte as char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
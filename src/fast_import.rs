@@ -0,0 +1,455 @@
+use std::{collections::{BTreeMap, HashMap}, io::{self, BufRead}};
+
+use crate::commit::{Commit, Signature};
+use crate::object::Object;
+use crate::object_id::ObjectId;
+use crate::object_store::{ObjectKind, ObjectStore};
+use crate::refs::{check_ref_name, Refs};
+use crate::tag::Tag;
+use crate::tree::{join_path, read_paths, write_paths, PathMap};
+
+fn invalid_stream(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("fast-import: {}", reason))
+}
+
+// Counts reported once a stream has been imported.
+#[derive(Debug, Default)]
+pub struct ImportStats {
+    pub blobs: usize,
+    pub commits: usize,
+    pub tags: usize,
+    pub refs: usize
+}
+
+// Line-oriented reader for the stream, with one line of lookahead and
+// support for the `data` command's raw byte payloads. Command lines are
+// text, except for identities, which are copied into objects as raw bytes.
+struct StreamReader<R: BufRead> {
+    reader: R,
+    peeked: Option<Vec<u8>>
+}
+
+fn line_text(line: Vec<u8>) -> io::Result<String> {
+    String::from_utf8(line).map_err(|_| invalid_stream("command line is not valid UTF-8"))
+}
+
+impl<R: BufRead> StreamReader<R> {
+    fn next_raw_line(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if let Some(line) = self.peeked.take() {
+            return Ok(Some(line));
+        }
+
+        let mut line = Vec::new();
+        if self.reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+        Ok(Some(line))
+    }
+
+    fn next_line(&mut self) -> io::Result<Option<String>> {
+        self.next_raw_line()?.map(line_text).transpose()
+    }
+
+    fn peek_raw_line(&mut self) -> io::Result<Option<&[u8]>> {
+        if self.peeked.is_none() {
+            self.peeked = self.next_raw_line()?;
+        }
+        Ok(self.peeked.as_deref())
+    }
+
+    fn peek_line(&mut self) -> io::Result<Option<&str>> {
+        match self.peek_raw_line()? {
+            Some(line) => std::str::from_utf8(line).map(Some).map_err(|_| invalid_stream("command line is not valid UTF-8")),
+            None => Ok(None),
+        }
+    }
+
+    // Consume the next line if it starts with `prefix`, returning the rest.
+    fn next_if_raw(&mut self, prefix: &str) -> io::Result<Option<Vec<u8>>> {
+        match self.peek_raw_line()? {
+            Some(line) if line.starts_with(prefix.as_bytes()) => {
+                let line = self.peeked.take().expect("line was just peeked");
+                Ok(Some(line[prefix.len()..].to_vec()))
+            },
+            _ => Ok(None),
+        }
+    }
+
+    fn next_if(&mut self, prefix: &str) -> io::Result<Option<String>> {
+        self.next_if_raw(prefix)?.map(line_text).transpose()
+    }
+
+    // `data <count>` followed by exactly that many bytes, or the delimited
+    // `data <<<delimiter>` form. A single trailing LF is optional.
+    fn read_data(&mut self) -> io::Result<Vec<u8>> {
+        let header = self.next_line()?.ok_or_else(|| invalid_stream("expected a data command"))?;
+        let spec = header.strip_prefix("data ").ok_or_else(|| invalid_stream(&format!("expected data, found '{}'", header)))?;
+
+        if let Some(delimiter) = spec.strip_prefix("<<") {
+            let mut data = Vec::new();
+            loop {
+                let line = self.next_raw_line()?.ok_or_else(|| invalid_stream("unterminated delimited data"))?;
+                if line == delimiter.as_bytes() {
+                    return Ok(data);
+                }
+                data.extend(line);
+                data.push(b'\n');
+            }
+        }
+
+        let count: usize = spec.parse().map_err(|_| invalid_stream(&format!("bad data length '{}'", spec)))?;
+        let mut data = vec![0u8; count];
+        self.reader.read_exact(&mut data)?;
+        if self.reader.fill_buf()?.first() == Some(&b'\n') {
+            self.reader.consume(1);
+        }
+        Ok(data)
+    }
+}
+
+// Creates blobs, trees, commits and tags from a `git fast-import` stream
+// through the regular object writers, then points the refs it names at the
+// results.
+pub struct FastImporter<'a> {
+    store: &'a ObjectStore,
+    refs: &'a Refs,
+    marks: HashMap<u64, ObjectId>,
+    branches: BTreeMap<String, ObjectId>,
+    stats: ImportStats
+}
+
+impl<'a> FastImporter<'a> {
+    pub fn new(store: &'a ObjectStore, refs: &'a Refs) -> Self {
+        Self {
+            store,
+            refs,
+            marks: HashMap::new(),
+            branches: BTreeMap::new(),
+            stats: ImportStats::default()
+        }
+    }
+
+    pub fn import<R: BufRead>(mut self, reader: R) -> io::Result<ImportStats> {
+        let mut stream = StreamReader { reader, peeked: None };
+
+        while let Some(line) = stream.next_line()? {
+            let (command, argument) = line.split_once(' ').unwrap_or((line.as_str(), ""));
+            match command {
+                "" => {},
+                _ if command.starts_with('#') => {},
+                "blob" => self.blob(&mut stream)?,
+                "commit" => self.commit(&mut stream, argument)?,
+                "tag" => self.tag(&mut stream, argument)?,
+                "reset" => self.reset(&mut stream, argument)?,
+                "progress" => println!("progress {}", argument),
+                "checkpoint" | "option" => {},
+                "feature" if matches!(argument, "done" | "date-format=raw") => {},
+                "done" => break,
+                _ => return Err(invalid_stream(&format!("unsupported command '{}'", line))),
+            }
+        }
+
+        for (name, oid) in &self.branches {
+            self.refs.update(name, oid)?;
+        }
+        self.stats.refs = self.branches.len();
+        Ok(self.stats)
+    }
+
+    fn read_mark(&mut self, stream: &mut StreamReader<impl BufRead>) -> io::Result<Option<u64>> {
+        let mark = match stream.next_if("mark :")? {
+            Some(mark) => Some(mark.parse().map_err(|_| invalid_stream(&format!("bad mark ':{}'", mark)))?),
+            None => None,
+        };
+        // The original ids are informational only.
+        stream.next_if("original-oid ")?;
+        Ok(mark)
+    }
+
+    fn set_mark(&mut self, mark: Option<u64>, oid: ObjectId) {
+        if let Some(mark) = mark {
+            self.marks.insert(mark, oid);
+        }
+    }
+
+    fn blob(&mut self, stream: &mut StreamReader<impl BufRead>) -> io::Result<()> {
+        let mark = self.read_mark(stream)?;
+        let data = stream.read_data()?;
+        let oid = self.store.write(ObjectKind::Blob, &data)?;
+        self.set_mark(mark, oid);
+        self.stats.blobs += 1;
+        Ok(())
+    }
+
+    // A `:mark`, a full object id, a branch written earlier in the stream
+    // or an existing ref.
+    fn resolve(&self, spec: &str) -> io::Result<ObjectId> {
+        let spec = spec.strip_suffix("^0").unwrap_or(spec);
+        if let Some(mark) = spec.strip_prefix(':') {
+            let mark: u64 = mark.parse().map_err(|_| invalid_stream(&format!("bad mark '{}'", spec)))?;
+            return self.marks.get(&mark).copied().ok_or_else(|| invalid_stream(&format!("mark :{} is not set", mark)));
+        }
+        if let Some(oid) = self.branches.get(spec) {
+            return Ok(*oid);
+        }
+        if spec.len() == self.store.format().hex_len() {
+            if let Ok(oid) = spec.parse() {
+                return Ok(oid);
+            }
+        }
+        self.refs.resolve(spec)?.ok_or_else(|| invalid_stream(&format!("cannot resolve '{}'", spec)))
+    }
+
+    fn commit_tree(&self, oid: &ObjectId) -> io::Result<PathMap> {
+        match Object::read(self.store, oid)? {
+            Object::Commit(commit) => read_paths(self.store, &commit.tree),
+            other => Err(invalid_stream(&format!("{} is a {}, not a commit", oid, other.kind()))),
+        }
+    }
+
+    fn commit(&mut self, stream: &mut StreamReader<impl BufRead>, ref_name: &str) -> io::Result<()> {
+        check_ref_name(ref_name)?;
+        let mark = self.read_mark(stream)?;
+        let author = stream.next_if_raw("author ")?.map(|author| Signature::parse(&author)).transpose()?;
+        let committer = stream.next_if_raw("committer ")?.ok_or_else(|| invalid_stream("commit is missing a committer"))?;
+        let committer = Signature::parse(&committer)?;
+        let mut extra_headers = Vec::new();
+        if let Some(encoding) = stream.next_if("encoding ")? {
+            extra_headers.push(("encoding".to_string(), encoding.into_bytes()));
+        }
+        // Stored as given: with an `encoding` header it need not be UTF-8.
+        let message = stream.read_data()?;
+
+        // Without `from`, a branch continues from its current tip.
+        let mut parents = Vec::new();
+        match stream.next_if("from ")? {
+            Some(from) => parents.push(self.resolve(&from)?),
+            None => parents.extend(self.branches.get(ref_name).copied().or(self.refs.resolve(ref_name)?)),
+        }
+        while let Some(merge) = stream.next_if("merge ")? {
+            parents.push(self.resolve(&merge)?);
+        }
+
+        let mut paths = match parents.first() {
+            Some(parent) => self.commit_tree(parent)?,
+            None => PathMap::new(),
+        };
+        while let Some(line) = stream.peek_line()? {
+            let line = line.to_string();
+            let Some((op, rest)) = line.split_once(' ').or((line == "deleteall").then_some(("deleteall", ""))) else {
+                break;
+            };
+            match op {
+                "M" => {
+                    stream.next_line()?;
+                    self.file_modify(stream, &mut paths, rest)?;
+                },
+                "D" => {
+                    stream.next_line()?;
+                    let (path, _) = parse_path(rest, true)?;
+                    remove_path(&mut paths, &path);
+                },
+                "C" | "R" => {
+                    stream.next_line()?;
+                    let (source, target) = parse_path(rest, false)?;
+                    let (target, _) = parse_path(target.trim_start_matches(' '), true)?;
+                    copy_path(&mut paths, &source, &target, op == "R")?;
+                },
+                "deleteall" => {
+                    stream.next_line()?;
+                    paths.clear();
+                },
+                _ => break,
+            }
+        }
+
+        let tree = write_paths(self.store, &paths)?;
+        let commit = Commit { tree, parents, author: author.unwrap_or_else(|| committer.clone()), committer, extra_headers, message, separator: true };
+        let oid = self.store.write(ObjectKind::Commit, &commit.to_bytes())?;
+        self.set_mark(mark, oid);
+        self.branches.insert(ref_name.to_string(), oid);
+        self.stats.commits += 1;
+        Ok(())
+    }
+
+    // `M <mode> <dataref> <path>`, where the data is a mark, an object id or
+    // `inline` data that follows the command.
+    fn file_modify(&mut self, stream: &mut StreamReader<impl BufRead>, paths: &mut PathMap, rest: &str) -> io::Result<()> {
+        let mut parts = rest.splitn(3, ' ');
+        let (Some(mode), Some(dataref), Some(path)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid_stream(&format!("malformed file command 'M {}'", rest)));
+        };
+        let mode = match u32::from_str_radix(mode, 8).map_err(|_| invalid_stream(&format!("bad mode '{}'", mode)))? {
+            0o644 => 0o100644,
+            0o755 => 0o100755,
+            mode => mode,
+        };
+        let (path, _) = parse_path(path, true)?;
+
+        let oid = match dataref {
+            "inline" => {
+                let data = stream.read_data()?;
+                self.stats.blobs += 1;
+                self.store.write(ObjectKind::Blob, &data)?
+            },
+            dataref => self.resolve(dataref)?,
+        };
+
+        remove_path(paths, &path);
+        if mode == 0o040000 {
+            // A whole directory taken from an existing tree.
+            for (child, entry) in read_paths(self.store, &oid)? {
+                paths.insert(join_path(&path, &child), entry);
+            }
+        } else {
+            paths.insert(path, (mode, oid));
+        }
+        Ok(())
+    }
+
+    fn tag(&mut self, stream: &mut StreamReader<impl BufRead>, name: &str) -> io::Result<()> {
+        check_ref_name(name)?;
+        let mark = self.read_mark(stream)?;
+        let from = stream.next_if("from ")?.ok_or_else(|| invalid_stream("tag is missing from"))?;
+        let object = self.resolve(&from)?;
+        stream.next_if("original-oid ")?;
+        let tagger = stream.next_if_raw("tagger ")?.map(|tagger| Signature::parse(&tagger)).transpose()?;
+        let message = stream.read_data()?;
+
+        let (kind, _) = self.store.read(&object)?;
+        let tag = Tag { object, kind, name: name.to_string(), tagger, extra_headers: Vec::new(), message, separator: true };
+        let oid = self.store.write(ObjectKind::Tag, &tag.to_bytes())?;
+        self.set_mark(mark, oid);
+        self.branches.insert(format!("refs/tags/{}", name), oid);
+        self.stats.tags += 1;
+        Ok(())
+    }
+
+    fn reset(&mut self, stream: &mut StreamReader<impl BufRead>, ref_name: &str) -> io::Result<()> {
+        check_ref_name(ref_name)?;
+        match stream.next_if("from ")? {
+            Some(from) => {
+                let oid = self.resolve(&from)?;
+                self.branches.insert(ref_name.to_string(), oid);
+            },
+            None => {
+                self.branches.remove(ref_name);
+            },
+        }
+        Ok(())
+    }
+}
+
+// Read a possibly C-quoted path. Unquoted paths run to the end of the line
+// when `last` is set, and to the first space otherwise. Returns the path
+// and whatever follows it.
+fn parse_path(input: &str, last: bool) -> io::Result<(Vec<u8>, &str)> {
+    let Some(quoted) = input.strip_prefix('"') else {
+        return Ok(match (last, input.split_once(' ')) {
+            (false, Some((path, rest))) => (path.as_bytes().to_vec(), rest),
+            _ => (input.as_bytes().to_vec(), ""),
+        });
+    };
+
+    let mut bytes = Vec::new();
+    let mut chars = quoted.char_indices();
+    while let Some((position, c)) = chars.next() {
+        match c {
+            '"' => return Ok((bytes, &quoted[position + 1..])),
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('n') => bytes.push(b'\n'),
+                Some('t') => bytes.push(b'\t'),
+                Some(digit @ '0'..='7') => {
+                    let mut value = digit.to_digit(8).expect("octal digit");
+                    for _ in 0..2 {
+                        let (_, digit) = chars.next().ok_or_else(|| invalid_stream("truncated octal escape"))?;
+                        value = value * 8 + digit.to_digit(8).ok_or_else(|| invalid_stream("bad octal escape"))?;
+                    }
+                    bytes.push(value as u8);
+                },
+                Some(c) => bytes.extend(c.to_string().as_bytes()),
+                None => return Err(invalid_stream("unterminated quoted path")),
+            },
+            c => bytes.extend(c.to_string().as_bytes()),
+        }
+    }
+    Err(invalid_stream("unterminated quoted path"))
+}
+
+// Remove a file, or a whole directory when `path` names one.
+fn remove_path(paths: &mut PathMap, path: &[u8]) {
+    let prefix = [path, b"/"].concat();
+    paths.retain(|candidate, _| candidate != path && !candidate.starts_with(&prefix));
+}
+
+fn copy_path(paths: &mut PathMap, source: &[u8], target: &[u8], rename: bool) -> io::Result<()> {
+    let prefix = [source, b"/"].concat();
+    let copied: Vec<(Vec<u8>, (u32, ObjectId))> = paths.iter()
+        .filter_map(|(path, entry)| {
+            if path == source {
+                Some((target.to_vec(), *entry))
+            } else {
+                path.strip_prefix(prefix.as_slice()).map(|rest| (join_path(target, rest), *entry))
+            }
+        })
+        .collect();
+    if copied.is_empty() {
+        return Err(invalid_stream(&format!("path '{}' not in the branch", String::from_utf8_lossy(source))));
+    }
+
+    if rename {
+        remove_path(paths, source);
+    }
+    remove_path(paths, target);
+    paths.extend(copied);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fast_export::FastExporter;
+    use crate::object_id::ObjectFormat;
+    use std::fs;
+
+    #[test]
+    fn test_import_export_round_trip() {
+        let dir = std::env::temp_dir().join(format!("warp-fast-import-{}", std::process::id()));
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        let store = ObjectStore::new(dir.join("objects"), ObjectFormat::Sha1);
+        let refs = Refs::new(dir.clone());
+        // The second commit has a Latin-1 message and renames into a
+        // directory whose name is not UTF-8.
+        let stream: &[u8] = b"commit refs/heads/main\nmark :1\ncommitter C <c@x> 1 +0000\ndata 4\none\n\
+            M 644 inline a/b\ndata 3\nabc\n\n\
+            commit refs/heads/main\nmark :2\ncommitter C <c@x> 2 +0000\nencoding ISO-8859-1\n\
+            data <<EOF\ntw\xe9\nEOF\nR a \"c\\351\"\n\n\
+            tag v1\nfrom :2\ntagger C <c@x> 3 +0000\ndata 8\nrelease\ndone\n";
+
+        let stats = FastImporter::new(&store, &refs).import(stream).unwrap();
+        assert_eq!((stats.blobs, stats.commits, stats.tags, stats.refs), (1, 2, 1, 2));
+        let tip = refs.resolve("refs/heads/main").unwrap().unwrap();
+        let Object::Commit(commit) = Object::read(&store, &tip).unwrap() else { panic!("tip is not a commit") };
+        assert_eq!(commit.message, b"tw\xe9\n");
+        assert_eq!(read_paths(&store, &commit.tree).unwrap().into_keys().collect::<Vec<_>>(), vec![b"c\xe9/b".to_vec()]);
+
+        // Exporting and importing again must reproduce the same objects.
+        let mut exported = Vec::new();
+        FastExporter::new(&store, &mut exported).export(&refs.list().unwrap()).unwrap();
+        let copy = Refs::new(dir.join("copy"));
+        FastImporter::new(&store, &copy).import(exported.as_slice()).unwrap();
+        assert_eq!(copy.list().unwrap(), refs.list().unwrap());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_path() {
+        assert_eq!(parse_path("src/main.rs", true).unwrap(), (b"src/main.rs".to_vec(), ""));
+        assert_eq!(parse_path("old name new", false).unwrap(), (b"old".to_vec(), "name new"));
+        assert_eq!(parse_path("\"a \\\"b\\\"\\n\\303\\251\" rest", false).unwrap(), ("a \"b\"\né".as_bytes().to_vec(), " rest"));
+    }
+}
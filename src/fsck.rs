@@ -0,0 +1,398 @@
+use std::{collections::{HashMap, HashSet}, fs, io, path::Path};
+
+use crate::chunking::Manifest;
+use crate::index::{generic_index, index_file_exists, CacheTreeEntry, IndexEntry, WarpIndex};
+use crate::object::Object;
+use crate::object_id::{ObjectFormat, ObjectId};
+use crate::object_store::{ObjectKind, ObjectStore};
+use crate::reachable::collect_index_roots;
+use crate::refs::Refs;
+use crate::tree::Tree;
+
+// Modes git itself writes into trees.
+const VALID_MODES: [u32; 5] = [0o100644, 0o100755, 0o120000, 0o040000, 0o160000];
+// Group-writable files, written by very old versions of git and still
+// accepted by it.
+const LEGACY_MODE: u32 = 0o100664;
+
+// The outcome of a repository check. Any problem means the repository is
+// corrupt; warnings are oddities git tolerates, and dangling objects are
+// merely unreferenced.
+#[derive(Debug, Default)]
+pub struct FsckReport {
+    pub checked: usize,
+    pub problems: Vec<String>,
+    pub warnings: Vec<String>,
+    pub dangling: Vec<(ObjectKind, ObjectId)>
+}
+
+impl FsckReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+// A reference from one object to another, checked once every object has
+// been read.
+struct Link {
+    from: (ObjectKind, ObjectId),
+    to: ObjectId,
+    expected: ObjectKind
+}
+
+// Re-hash every loose and packed object, validate trees, commits and tags,
+// check the links between them, then verify the index and its cache-tree.
+pub fn check(store: &ObjectStore, refs: &Refs) -> io::Result<FsckReport> {
+    let mut report = FsckReport::default();
+    let mut kinds: HashMap<ObjectId, ObjectKind> = HashMap::new();
+    let mut links = Vec::new();
+    let mut manifests = Vec::new();
+
+    check_pack_files(store, &mut report)?;
+
+    for oid in store.loose_oids()? {
+        let object = store.read_loose(&oid);
+        manifests.extend(check_object(store, oid, "loose", object, &mut kinds, &mut links, &mut report).map(|manifest| (oid, manifest)));
+    }
+    for oid in store.packed_oids()? {
+        let object = store.read_packed(&oid).and_then(|object| {
+            object.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "listed in the pack index but unreadable"))
+        });
+        manifests.extend(check_object(store, oid, "packed", object, &mut kinds, &mut links, &mut report).map(|manifest| (oid, manifest)));
+    }
+
+    // Objects borrowed from alternates are not checked, only looked up.
+    let exists = |oid: &ObjectId| kinds.contains_key(oid) || store.contains(oid);
+    let index_roots = check_index(store, &exists, &mut report)?;
+
+    // Chunked files link on to their chunks through the manifest. Only
+    // blobs a tree or the index lists as a file are manifests: a chunk is
+    // raw content, even when it starts with the manifest magic.
+    let files: HashSet<ObjectId> = links.iter().filter(|link| link.expected == ObjectKind::Blob).map(|link| link.to)
+        .chain(index_roots.iter().copied())
+        .collect();
+    for (oid, manifest) in manifests.into_iter().filter(|(oid, _)| files.contains(oid)) {
+        match manifest {
+            Ok(manifest) => links.extend(manifest.chunks.iter().map(|(chunk, _)| Link {
+                from: (ObjectKind::Blob, oid), to: *chunk, expected: ObjectKind::Blob
+            })),
+            Err(e) => report.problems.push(format!("blob {}: {}", oid, e)),
+        }
+    }
+
+    let mut referenced = HashSet::new();
+    for link in &links {
+        referenced.insert(link.to);
+        match kinds.get(&link.to) {
+            None if store.contains(&link.to) => {},
+            None => report.problems.push(format!("broken link from {} {} to {} {}", link.from.0, link.from.1, link.expected, link.to)),
+            Some(kind) if *kind != link.expected => report.problems.push(format!(
+                "{} {} expects {} to be a {} but it is a {}", link.from.0, link.from.1, link.to, link.expected, kind
+            )),
+            Some(_) => {},
+        }
+    }
+
+    let mut roots = HashSet::new();
+    for (name, oid) in refs.list()? {
+        if !exists(&oid) {
+            report.problems.push(format!("{} points to missing object {}", name, oid));
+        }
+        roots.insert(oid);
+    }
+    if let Some(oid) = refs.read_head()? {
+        if !exists(&oid) {
+            report.problems.push(format!("HEAD points to missing object {}", oid));
+        }
+        roots.insert(oid);
+    }
+    roots.extend(refs.reflog_oids()?);
+    roots.extend(index_roots);
+
+    report.dangling = kinds.iter()
+        .filter(|(oid, _)| !referenced.contains(*oid) && !roots.contains(*oid))
+        .map(|(oid, kind)| (*kind, *oid))
+        .collect();
+    report.dangling.sort_by_key(|(_, oid)| *oid);
+
+    Ok(report)
+}
+
+// Verify one copy of an object: it must inflate, hash back to its name and
+// parse as its type. Links to other objects are recorded for later, and a
+// blob starting with the manifest magic is returned parsed, for the caller
+// to follow if the blob turns out to be a file.
+fn check_object(
+    store: &ObjectStore,
+    oid: ObjectId,
+    source: &str,
+    object: io::Result<(ObjectKind, Vec<u8>)>,
+    kinds: &mut HashMap<ObjectId, ObjectKind>,
+    links: &mut Vec<Link>,
+    report: &mut FsckReport
+) -> Option<io::Result<Manifest>> {
+    report.checked += 1;
+    let (kind, bytes) = match object {
+        Ok(object) => object,
+        Err(e) => {
+            report.problems.push(format!("{} object {}: {}", source, oid, e));
+            return None;
+        }
+    };
+
+    let actual = store.hash(kind, &bytes);
+    if actual != oid {
+        report.problems.push(format!("hash mismatch for {} object {} (content hashes to {})", source, oid, actual));
+        return None;
+    }
+
+    // A loose and a packed copy of the same object only need parsing once.
+    if kinds.insert(oid, kind).is_some() {
+        return None;
+    }
+
+    let from = (kind, oid);
+    match Object::parse(kind, &bytes, store.format()) {
+        Err(e) => report.problems.push(format!("{} {}: {}", kind, oid, e)),
+        Ok(Object::Blob(_)) => return Manifest::detect(&bytes).transpose(),
+        Ok(Object::Tree(tree)) => {
+            check_tree(oid, &tree, report);
+            for entry in &tree.entries {
+                // Submodule commits live in another repository.
+                if entry.kind() != ObjectKind::Commit {
+                    links.push(Link { from, to: entry.oid, expected: entry.kind() });
+                }
+            }
+        },
+        Ok(Object::Commit(commit)) => {
+            links.push(Link { from, to: commit.tree, expected: ObjectKind::Tree });
+            links.extend(commit.parents.iter().map(|parent| Link { from, to: *parent, expected: ObjectKind::Commit }));
+        },
+        Ok(Object::Tag(tag)) => links.push(Link { from, to: tag.object, expected: tag.kind }),
+    }
+    None
+}
+
+// Trees must list each name once, in git's order (directories sort as if
+// their name ended in '/'), with one of the modes git writes. Modes git
+// only tolerates, such as 100664 or a zero-padded 040000, are warnings.
+fn check_tree(oid: ObjectId, tree: &Tree, report: &mut FsckReport) {
+    let sort_key = |name: &[u8], kind: ObjectKind| {
+        let mut key = name.to_vec();
+        if kind == ObjectKind::Tree {
+            key.push(b'/');
+        }
+        key
+    };
+
+    let mut previous: Option<(&[u8], Vec<u8>)> = None;
+    for entry in &tree.entries {
+        let name = String::from_utf8_lossy(&entry.name);
+        if entry.mode == LEGACY_MODE {
+            report.warnings.push(format!("tree {} has group-writable mode {:o} for '{}'", oid, entry.mode, name));
+        } else if !VALID_MODES.contains(&entry.mode) {
+            report.problems.push(format!("tree {} has bad mode {:o} for '{}'", oid, entry.mode, name));
+        } else if entry.raw_mode.is_some() {
+            report.warnings.push(format!("tree {} has zero-padded mode {} for '{}'", oid, String::from_utf8_lossy(&entry.mode_bytes()), name));
+        }
+        if entry.name.is_empty() || entry.name.contains(&b'/') || entry.name == b"." || entry.name == b".." {
+            report.problems.push(format!("tree {} has invalid entry name '{}'", oid, name));
+        }
+
+        let key = sort_key(&entry.name, entry.kind());
+        if let Some((previous_name, previous_key)) = &previous {
+            if *previous_name == entry.name.as_slice() {
+                report.problems.push(format!("tree {} has duplicate entry '{}'", oid, name));
+            } else if *previous_key > key {
+                report.problems.push(format!("tree {} is not sorted: '{}' comes before '{}'", oid, String::from_utf8_lossy(previous_name), name));
+            }
+        }
+        previous = Some((&entry.name, key));
+    }
+}
+
+// Each pack and its index end with a checksum of everything before it, and
+// the index repeats the checksum of its pack.
+fn check_pack_files(store: &ObjectStore, report: &mut FsckReport) -> io::Result<()> {
+    let width = store.format().raw_len();
+    let entries = match fs::read_dir(store.pack_dir()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    let mut pack_paths = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) == Some("pack") {
+            pack_paths.push(path);
+        }
+    }
+    pack_paths.sort();
+
+    for pack_path in pack_paths {
+        let Some(pack_checksum) = verify_trailer(&pack_path, store.format(), report)? else {
+            continue;
+        };
+
+        let idx_path = pack_path.with_extension("idx");
+        if !idx_path.is_file() {
+            report.problems.push(format!("{} has no index", pack_path.display()));
+            continue;
+        }
+        if verify_trailer(&idx_path, store.format(), report)?.is_some() {
+            let idx = fs::read(&idx_path)?;
+            if idx[idx.len() - 2 * width..idx.len() - width] != pack_checksum[..] {
+                report.problems.push(format!("{} does not describe {}", idx_path.display(), pack_path.display()));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Check the trailing checksum of a file, returning it when it matches.
+fn verify_trailer(path: &Path, format: ObjectFormat, report: &mut FsckReport) -> io::Result<Option<Vec<u8>>> {
+    let width = format.raw_len();
+    let data = fs::read(path)?;
+    if data.len() < 2 * width {
+        report.problems.push(format!("{} is truncated", path.display()));
+        return Ok(None);
+    }
+
+    let (content, trailer) = data.split_at(data.len() - width);
+    if format.digest(content).as_bytes() != trailer {
+        report.problems.push(format!("{} checksum mismatch", path.display()));
+        return Ok(None);
+    }
+    Ok(Some(trailer.to_vec()))
+}
+
+// Verify the index checksum, that every staged blob exists and that the
+// `TREE` extension agrees with the entries. Returns the ids the index keeps
+// alive.
+fn check_index(store: &ObjectStore, exists: &dyn Fn(&ObjectId) -> bool, report: &mut FsckReport) -> io::Result<Vec<ObjectId>> {
+    if !index_file_exists() {
+        return Ok(Vec::new());
+    }
+
+    let index_path = generic_index();
+    if verify_trailer(&index_path, store.format(), report)?.is_none() {
+        return Ok(Vec::new());
+    }
+
+    let bytes = fs::read(&index_path)?;
+    let index = match WarpIndex::parse(&mut io::Cursor::new(bytes.as_slice()), store.format()) {
+        Ok(index) => index,
+        Err(e) => {
+            report.problems.push(format!("unable to parse the index: {}", e));
+            return Ok(Vec::new());
+        }
+    };
+
+    let mut roots = Vec::new();
+    collect_index_roots(&index, &mut roots);
+    for entry in index.entries.iter().filter(|entry| entry.mode != 0o160000) {
+        if !exists(&entry.sha) {
+            report.problems.push(format!("index entry '{}' points to missing blob {}", entry.path, entry.sha));
+        }
+    }
+
+    if let Some(extension) = &index.extensions {
+        check_cache_tree(store, &extension.extension_data, "", &index.entries, report);
+    }
+    Ok(roots)
+}
+
+// A cache-tree node is valid when its tree holds exactly the index entries
+// below its directory, and so on for every subtree. Invalidated nodes make
+// no claim, but their subtrees still do.
+fn check_cache_tree(store: &ObjectStore, node: &CacheTreeEntry, dir: &str, entries: &[IndexEntry], report: &mut FsckReport) {
+    let label = if dir.is_empty() { "the root" } else { dir };
+    if node.is_valid() {
+        check_cache_tree_node(store, node, dir, label, entries, report);
+    }
+
+    let subtrees = node.subtrees.as_deref().unwrap_or_default();
+    if node.subtree_count as usize != subtrees.len() {
+        report.problems.push(format!("cache-tree for {} has a wrong subtree count", label));
+    }
+    for subtree in subtrees {
+        let name = String::from_utf8_lossy(subtree.name());
+        let path = if dir.is_empty() { name.to_string() } else { format!("{}/{}", dir, name) };
+        check_cache_tree(store, subtree, &path, entries, report);
+    }
+}
+
+fn check_cache_tree_node(store: &ObjectStore, node: &CacheTreeEntry, dir: &str, label: &str, entries: &[IndexEntry], report: &mut FsckReport) {
+    let mut expected: Vec<(String, ObjectId)> = entries.iter()
+        .filter(|entry| dir.is_empty() || entry.path.starts_with(&format!("{}/", dir)))
+        .map(|entry| (entry.path.clone(), entry.sha))
+        .collect();
+    expected.sort();
+
+    if node.entry_count as usize != expected.len() {
+        report.problems.push(format!(
+            "cache-tree for {} counts {} entries but the index has {}", label, node.entry_count, expected.len()
+        ));
+    }
+
+    let mut actual = Vec::new();
+    match tree_files(store, node.sha, dir, &mut actual) {
+        Err(e) => report.problems.push(format!("cache-tree for {} points to unreadable tree {}: {}", label, node.sha, e)),
+        Ok(()) => {
+            actual.sort();
+            if actual != expected {
+                report.problems.push(format!("cache-tree for {} does not match the index", label));
+            }
+        },
+    }
+}
+
+// Every non-tree entry below `oid`, with its path from the repository root.
+fn tree_files(store: &ObjectStore, oid: ObjectId, prefix: &str, files: &mut Vec<(String, ObjectId)>) -> io::Result<()> {
+    let tree = match Object::read(store, &oid)? {
+        Object::Tree(tree) => tree,
+        other => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is a {}, not a tree", oid, other.kind()))),
+    };
+
+    for entry in tree.entries {
+        let name = String::from_utf8_lossy(&entry.name);
+        let path = if prefix.is_empty() { name.to_string() } else { format!("{}/{}", prefix, name) };
+        if entry.kind() == ObjectKind::Tree {
+            tree_files(store, entry.oid, &path, files)?;
+        } else {
+            files.push((path, entry.oid));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::TreeEntry;
+
+    #[test]
+    fn test_check_tree_order_and_modes() {
+        let oid = ObjectId::null(ObjectFormat::Sha1);
+        let entry = |mode: u32, name: &str| TreeEntry::new(mode, name.as_bytes(), oid);
+
+        // "foo.c" sorts before the directory "foo" because '.' < '/'.
+        let mut report = FsckReport::default();
+        check_tree(oid, &Tree { entries: vec![entry(0o100644, "foo.c"), entry(0o040000, "foo")] }, &mut report);
+        assert!(report.is_ok(), "{:?}", report.problems);
+
+        let mut report = FsckReport::default();
+        check_tree(oid, &Tree { entries: vec![entry(0o040000, "foo"), entry(0o100644, "foo.c")] }, &mut report);
+        check_tree(oid, &Tree { entries: vec![entry(0o100644, "a"), entry(0o100644, "a")] }, &mut report);
+        check_tree(oid, &Tree { entries: vec![entry(0o100600, "b")] }, &mut report);
+        assert_eq!(report.problems.len(), 3);
+
+        // Modes found in old histories are only warned about.
+        let mut report = FsckReport::default();
+        let padded = TreeEntry { raw_mode: Some(b"040000".to_vec()), ..entry(0o040000, "dir") };
+        check_tree(oid, &Tree { entries: vec![entry(0o100664, "b"), padded] }, &mut report);
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert_eq!(report.warnings.len(), 2);
+    }
+}
@@ -0,0 +1,133 @@
+use std::{fs, io, path::Path};
+
+use crate::config::Config;
+use crate::object_id::ObjectFormat;
+use crate::object_store::ObjectStore;
+use crate::pack_reader::Pack;
+use crate::refs::Refs;
+
+// Counts reported once a git repository has been imported.
+#[derive(Debug, Default)]
+pub struct ImportStats {
+    pub packs: usize,
+    pub loose: usize,
+    pub refs: usize
+}
+
+// Copy the objects, refs and HEAD of the git directory `git_dir` into
+// `store` and `refs`. Packs are copied as they are and loose objects are
+// re-hashed on the way in, so every object keeps its id.
+pub fn import_repository(store: &ObjectStore, refs: &Refs, git_dir: &Path) -> io::Result<ImportStats> {
+    if !git_dir.join("objects").is_dir() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} is not a git repository", git_dir.display())));
+    }
+
+    let git_config = Config::load(git_dir.join("config"))?;
+    let git_format: ObjectFormat = git_config.get("extensions.objectformat").map(str::parse).transpose()?.unwrap_or_default();
+    if git_format != store.format() {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, format!(
+            "cannot import a {} repository into a {} repository", git_format, store.format()
+        )));
+    }
+
+    let mut stats = ImportStats::default();
+    let git_store = ObjectStore::new(git_dir.join("objects"), git_format).with_alternates()?;
+    for source in std::iter::once(&git_store).chain(git_store.alternates()) {
+        let mut idx_paths = Vec::new();
+        if source.pack_dir().is_dir() {
+            for entry in fs::read_dir(source.pack_dir())? {
+                let path = entry?.path();
+                if path.extension().and_then(|ext| ext.to_str()) == Some("idx") && path.with_extension("pack").is_file() {
+                    idx_paths.push(path);
+                }
+            }
+        }
+        for idx_path in idx_paths {
+            Pack::open(&idx_path, git_format)?;
+            if store.install_pack(&idx_path)? {
+                stats.packs += 1;
+            }
+        }
+
+        for oid in source.loose_oids()? {
+            let (kind, bytes) = source.read_loose(&oid)?;
+            if store.write(kind, &bytes)? != oid {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("object {} in {} is corrupt", oid, git_dir.display())));
+            }
+            stats.loose += 1;
+        }
+    }
+
+    let imported_refs = Refs::new(git_dir.to_path_buf()).list()?;
+    for (name, oid) in &imported_refs {
+        refs.update(name, oid)?;
+    }
+    stats.refs = imported_refs.len();
+
+    let head = fs::read_to_string(git_dir.join("HEAD"))?;
+    match head.trim().strip_prefix("ref: ") {
+        Some(target) => refs.update_symbolic("HEAD", target)?,
+        None => {
+            let oid = head.trim().parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("malformed HEAD in {}", git_dir.display())))?;
+            refs.update("HEAD", &oid)?
+        },
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_id::ObjectId;
+    use std::process::Command;
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git").arg("-C").arg(dir).args(args)
+            .env("GIT_CONFIG_GLOBAL", "/dev/null").env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_AUTHOR_NAME", "A U Thor").env("GIT_AUTHOR_EMAIL", "author@example.com")
+            .env("GIT_COMMITTER_NAME", "C O Mitter").env("GIT_COMMITTER_EMAIL", "committer@example.com")
+            .output().expect("git is installed");
+        assert!(output.status.success(), "git {:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn test_import_packed_and_loose_objects() {
+        let dir = std::env::temp_dir().join(format!("warp-git-import-{}", std::process::id()));
+        let work = dir.join("git");
+        fs::create_dir_all(&work).unwrap();
+        git(&work, &["init", "-q", "-b", "main"]);
+        fs::write(work.join("a.txt"), "one\n").unwrap();
+        git(&work, &["add", "a.txt"]);
+        git(&work, &["commit", "-q", "-m", "first"]);
+        git(&work, &["tag", "-a", "v1", "-m", "release"]);
+        // Pack everything so far, then leave the second commit loose.
+        git(&work, &["gc", "-q"]);
+        fs::write(work.join("b.txt"), "two\n").unwrap();
+        git(&work, &["add", "b.txt"]);
+        git(&work, &["commit", "-q", "-m", "second"]);
+
+        fs::create_dir_all(dir.join("warp").join("objects")).unwrap();
+        let store = ObjectStore::new(dir.join("warp").join("objects"), ObjectFormat::Sha1);
+        let refs = Refs::new(dir.join("warp"));
+        let stats = import_repository(&store, &refs, &work.join(".git")).unwrap();
+        assert_eq!((stats.packs, stats.refs), (1, 2));
+        assert!(stats.loose >= 3);
+
+        let objects = git(&work, &["cat-file", "--batch-all-objects", "--batch-check=%(objectname)"]);
+        for oid in objects.lines().map(|line| line.parse::<ObjectId>().unwrap()) {
+            let (kind, bytes) = store.read(&oid).unwrap();
+            assert_eq!(store.hash(kind, &bytes), oid);
+        }
+
+        let expected: Vec<(String, ObjectId)> = git(&work, &["for-each-ref", "--format=%(refname) %(objectname)"]).lines()
+            .map(|line| line.split_once(' ').unwrap())
+            .map(|(name, oid)| (name.to_string(), oid.parse().unwrap()))
+            .collect();
+        assert_eq!(refs.list().unwrap(), expected);
+        assert_eq!(refs.read_head().unwrap(), Some(git(&work, &["rev-parse", "HEAD"]).trim().parse().unwrap()));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,11 +1,10 @@
 use core::fmt;
-use std::{collections::{HashMap, HashSet}, env, ffi::CString, fmt::Debug, fs, io::{BufReader, Cursor, Read, Write}, os::unix::fs::MetadataExt, path::{self, PathBuf}};
-use flate2::{write::ZlibEncoder, Compression};
+use std::{cmp::Ordering, collections::{HashMap, HashSet}, fmt::Debug, fs, io::{self, BufRead, BufReader, Cursor, Read, Write}, os::unix::fs::MetadataExt, path::{Path, PathBuf}};
 use hex_literal::hex;
 use chrono::DateTime;
-use sha1::{Sha1, Digest};
-
 use crate::blob::Blob;
+use crate::object_id::{ObjectFormat, ObjectId};
+use crate::object_store::{ObjectKind, ObjectStore};
 #[allow(unused_variables)]
 #[allow(dead_code)]
 pub const INDEX_DATA: &[u8] = &hex!(
@@ -56,6 +55,62 @@ pub const NO_TREE: &[u8] = &hex!("
     255645bdf17b967d57cc8ecf6015ffae
     ");
 
+pub const INDEX_SIGNATURE: [u8; 4] = *b"DIRC";
+pub const TREE_SIGNATURE: [u8; 4] = *b"TREE";
+
+// Versions 3 and 4 are version 2 plus, respectively, a second flags word on
+// entries that need one, and paths prefix-compressed against the previous
+// entry.
+pub const SUPPORTED_VERSIONS: [u32; 3] = [2, 3, 4];
+
+// Bits of the extended flags word.
+pub const INTENT_TO_ADD: u16 = 0x2000;
+pub const SKIP_WORKTREE: u16 = 0x4000;
+
+const ASSUME_VALID_BIT: u16 = 0x8000;
+const EXTENDED_BIT: u16 = 0x4000;
+const STAGE_SHIFT: u16 = 12;
+
+// Paths of this many bytes or more store this value as their length and
+// are read up to their NUL terminator instead.
+pub const NAME_MASK: u16 = 0x0fff;
+
+// The 16-bit flags word of an entry: assume-valid, extended, a 2-bit merge
+// stage and the 12-bit length of the path.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EntryFlags {
+    pub assume_valid: bool,
+    pub extended: bool,
+    pub stage: u8,
+    pub name_length: u16
+}
+
+impl EntryFlags {
+    pub fn for_path(path: &str) -> Self {
+        Self { name_length: path.len().min(NAME_MASK as usize) as u16, ..Self::default() }
+    }
+
+    pub fn from_bits(bits: u16) -> Self {
+        Self {
+            assume_valid: bits & ASSUME_VALID_BIT != 0,
+            extended: bits & EXTENDED_BIT != 0,
+            stage: ((bits >> STAGE_SHIFT) & 0x3) as u8,
+            name_length: bits & NAME_MASK
+        }
+    }
+
+    pub fn to_bits(&self) -> u16 {
+        let mut bits = self.name_length.min(NAME_MASK) | ((self.stage as u16 & 0x3) << STAGE_SHIFT);
+        if self.assume_valid {
+            bits |= ASSUME_VALID_BIT;
+        }
+        if self.extended {
+            bits |= EXTENDED_BIT;
+        }
+        bits
+    }
+}
+
 #[derive(Clone, PartialEq, Eq)]
 pub struct IndexHeader {
     pub signature: [u8; 4],
@@ -98,16 +153,24 @@ impl TryFrom<&mut Cursor<&[u8]>> for IndexHeader {
     type Error = IndexParseError;
 
     fn try_from(reader: &mut Cursor<&[u8]>) -> Result<Self, Self::Error> {
+        let offset = reader.position();
+        let truncated = |reader: &Cursor<&[u8]>| IndexParseError::Truncated { offset: reader.get_ref().len() as u64 };
         let mut signature = [0u8; 4];
         let mut version = [0u8; 4];
         let mut index_count = [0u8; 4];
 
-        reader.read_exact(&mut signature).unwrap();
-        reader.read_exact(&mut version).unwrap();
-        reader.read_exact(&mut index_count).unwrap();
+        reader.read_exact(&mut signature).map_err(|_| truncated(reader))?;
+        if signature != INDEX_SIGNATURE {
+            return Err(IndexParseError::InvalidSignature { offset, found: signature });
+        }
+        reader.read_exact(&mut version).map_err(|_| truncated(reader))?;
+        reader.read_exact(&mut index_count).map_err(|_| truncated(reader))?;
 
         let version: u32 = u32::from_be_bytes(version);
         let index_count: u32 = u32::from_be_bytes(index_count);
+        if !SUPPORTED_VERSIONS.contains(&version) {
+            return Err(IndexParseError::UnsupportedVersion { offset: offset + 4, version });
+        }
 
         Ok(IndexHeader::new(signature, version, index_count))
     }
@@ -126,10 +189,12 @@ pub struct IndexEntry {
     pub uid: u32,
     pub gid: u32,
     pub filesize: u32,
-    pub sha: [u8; 20],
-    pub flags: u16,
+    pub sha: ObjectId,
+    pub flags: EntryFlags,
+    // Only written, and only allowed, in version 3 and later indexes.
+    pub extended_flags: u16,
     pub path: String
-    // 1 - 8 bytes nul bytes necessary to pad the entry.
+    // 1 - 8 bytes nul bytes necessary to pad the entry (none in version 4).
 }
 
 impl IndexEntry {
@@ -137,11 +202,7 @@ impl IndexEntry {
     pub fn entry_from_file(file: PathBuf) -> IndexEntry {
         let metadata = fs::metadata(&file).expect("Unable to get metadata about this file");
         let blob: Blob = Blob::new(file.clone());
-        let blob_sha: String = blob.hash_object().unwrap();
-        let sha_bytes = hex::decode(&blob_sha).expect("Invalid hex in SHA");
-        let mut sha = [0u8; 20];
-        sha.copy_from_slice(&sha_bytes);
-        blob.compress_to_object();
+        let sha = blob.compress_to_object().expect("Unable to write blob object");
 
         Self {
             ctime_seconds: metadata.ctime() as u32,
@@ -155,12 +216,47 @@ impl IndexEntry {
             gid: metadata.gid(),
             filesize: metadata.len() as u32,
             sha,
-            flags: file.to_string_lossy().len() as u16,
+            flags: EntryFlags::for_path(&file.to_string_lossy()),
+            extended_flags: 0,
             path: file.to_string_lossy().to_string(),
         }
     }
     
+    // An entry for content already in the object store, with no stat
+    // information, as merges record their stages.
+    pub fn staged(path: String, mode: u32, sha: ObjectId, stage: u8) -> IndexEntry {
+        Self {
+            ctime_seconds: 0,
+            ctime_nanoseconds: 0,
+            mtime_seconds: 0,
+            mtime_nanoseconds: 0,
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            filesize: 0,
+            sha,
+            flags: EntryFlags { stage, ..EntryFlags::for_path(&path) },
+            extended_flags: 0,
+            path,
+        }
+    }
+
+    // 0 for a merged entry; 1, 2 and 3 hold the common ancestor's, ours and
+    // theirs for a conflicted path.
+    pub fn stage(&self) -> u8 {
+        self.flags.stage
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
+        self.encode(2, "")
+    }
+
+    // The entry as written in an index of `version`. Version 4 stores the
+    // path as the number of bytes to drop from the end of `previous_path`
+    // followed by the bytes to append, and is not padded.
+    pub fn encode(&self, version: u32, previous_path: &str) -> Vec<u8> {
         let mut bytes = Vec::new();
 
         bytes.extend(&self.ctime_seconds.to_be_bytes());
@@ -178,8 +274,25 @@ impl IndexEntry {
 
         bytes.extend(&self.filesize.to_be_bytes());
 
-        bytes.extend(&self.sha);
-        bytes.extend(&self.flags.to_be_bytes());
+        bytes.extend(self.sha.as_bytes());
+        // The length and extended bit always describe what is written.
+        let flags = EntryFlags {
+            extended: version >= 3 && self.extended_flags != 0,
+            name_length: EntryFlags::for_path(&self.path).name_length,
+            ..self.flags
+        };
+        bytes.extend(&flags.to_bits().to_be_bytes());
+        if flags.extended {
+            bytes.extend(&self.extended_flags.to_be_bytes());
+        }
+
+        if version >= 4 {
+            let common = common_prefix(previous_path, &self.path);
+            encode_varint(&mut bytes, (previous_path.len() - common) as u64);
+            bytes.extend(&self.path.as_bytes()[common..]);
+            bytes.push(0);
+            return bytes;
+        }
 
         bytes.extend(self.path.as_bytes());
         bytes.push(0); // Null terminator
@@ -192,70 +305,143 @@ impl IndexEntry {
     }
 }
 
+// Length in bytes of the longest common prefix of two paths, kept on a
+// character boundary.
+fn common_prefix(a: &str, b: &str) -> usize {
+    let mut common = a.bytes().zip(b.bytes()).take_while(|(x, y)| x == y).count();
+    while !b.is_char_boundary(common) {
+        common -= 1;
+    }
+    common
+}
+
+// Version 4 prefix lengths use the same big-endian base-128 encoding as
+// OFS_DELTA offsets in packs, where every continuation adds one.
+fn encode_varint(out: &mut Vec<u8>, mut value: u64) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value != 0 {
+        value -= 1;
+        bytes.insert(0, 0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    out.extend(bytes);
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut byte = [0u8; 1];
+    reader.read_exact(&mut byte)?;
+    let mut value = (byte[0] & 0x7f) as u64;
+    while byte[0] & 0x80 != 0 {
+        reader.read_exact(&mut byte)?;
+        value = ((value + 1) << 7) | (byte[0] & 0x7f) as u64;
+    }
+    Ok(value)
+}
+
 
 impl Debug for IndexEntry {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("IndexEntry")
          .field("ctime_seconds", &DateTime::from_timestamp(self.ctime_seconds.into(), 0).unwrap())
-         .field("ctime_nanoseconds", &DateTime::from_timestamp(self.ctime_seconds.into(), self.ctime_nanoseconds.into()).unwrap())
+         .field("ctime_nanoseconds", &DateTime::from_timestamp(self.ctime_seconds.into(), self.ctime_nanoseconds).unwrap())
          .field("mtime_seconds", &DateTime::from_timestamp(self.mtime_seconds.into(), 0).unwrap())
-         .field("mtime_nanoseconds", &DateTime::from_timestamp(self.mtime_seconds.into(), self.mtime_nanoseconds.into()).unwrap())
+         .field("mtime_nanoseconds", &DateTime::from_timestamp(self.mtime_seconds.into(), self.mtime_nanoseconds).unwrap())
          .field("dev", &self.dev)
          .field("ino", &self.ino)
          .field("mode", &format!("{:o}", &self.mode))
          .field("uid", &self.uid)
          .field("gid", &self.gid)
          .field("filesize", &self.filesize)
-         .field("sha", &self.sha.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+         .field("sha", &self.sha.to_hex())
          .field("flags", &self.flags)
+         .field("extended_flags", &self.extended_flags)
          .field("path", &self.path)
          .finish()
     }
 }
 
-impl TryFrom<&mut Cursor<&[u8]>> for IndexEntry {
-    type Error = String; // TODO: I will write the error logic some other time lol(Everything seems to work fine for now).
+impl IndexEntry {
+    // Entries hold an object id as wide as the repository's object format.
+    pub fn parse(reader: &mut Cursor<&[u8]>, format: ObjectFormat) -> Result<Self, IndexParseError> {
+        IndexEntry::parse_version(reader, format, 2, "")
+    }
+
+    // Parse an entry of an index of `version`, where version 4 paths are
+    // relative to `previous_path`.
+    pub fn parse_version(reader: &mut Cursor<&[u8]>, format: ObjectFormat, version: u32, previous_path: &str) -> Result<Self, IndexParseError> {
+        let start = reader.position();
+        let truncated = || IndexParseError::TruncatedEntry { offset: start };
+        let invalid = |reason: String| IndexParseError::InvalidEntry { offset: start, reason };
 
-    fn try_from(reader: &mut Cursor<&[u8]>) -> Result<Self, Self::Error> {
         // The first 10 elements in the IndexEntry structs are all u32's.
         let mut buffer = [0u8; 40];
-        reader.read_exact(&mut buffer).unwrap();
+        reader.read_exact(&mut buffer).map_err(|_| truncated())?;
     
         // Map them to a vector of u32
-        let values = buffer.chunks_exact(4).map(|chunk| u32::from_be_bytes(chunk.try_into().unwrap())).collect::<Vec<u32>>();
-        let mut sha = [0u8; 20];
-        reader.read_exact(&mut sha).unwrap();
+        let values = buffer.chunks_exact(4).map(|chunk| u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]])).collect::<Vec<u32>>();
+        let mut sha = vec![0u8; format.raw_len()];
+        reader.read_exact(&mut sha).map_err(|_| truncated())?;
+        let sha = ObjectId::from_slice(&sha).ok_or_else(truncated)?;
 
         let mut flags: [u8; 2] = [0u8; 2];
-        reader.read_exact(&mut flags).unwrap();
-
-        let pathname_length: u16 = u16::from_be_bytes(flags);
-
-        let mut path = vec![0u8; pathname_length as usize];
-        reader.read_exact(&mut path).unwrap();
+        reader.read_exact(&mut flags).map_err(|_| truncated())?;
+        let flags = EntryFlags::from_bits(u16::from_be_bytes(flags));
 
-        let mut single_byte = [0u8; 1];
-
-        // Read the variable null bytes padding.
-        while let Ok(_) = reader.read_exact(&mut single_byte) {
-            if single_byte[0] != 0 {
-                let current_pos = reader.position();
-                reader.set_position(current_pos - 1);
-                break;
+        let mut extended_flags = [0u8; 2];
+        if flags.extended {
+            if version < 3 {
+                return Err(invalid(format!("extended flags are not allowed in a version {} index", version)));
             }
+            reader.read_exact(&mut extended_flags).map_err(|_| truncated())?;
         }
+        let extended_flags = u16::from_be_bytes(extended_flags);
+
+        let path = if version >= 4 {
+            let strip = read_varint(reader).map_err(|_| truncated())? as usize;
+            if strip > previous_path.len() {
+                return Err(invalid(format!("strips {} bytes from the {} byte path before it", strip, previous_path.len())));
+            }
+            let mut suffix = Vec::new();
+            reader.read_until(0, &mut suffix).map_err(|_| truncated())?;
+            if suffix.pop() != Some(0) {
+                return Err(truncated());
+            }
+            [&previous_path.as_bytes()[..previous_path.len() - strip], &suffix[..]].concat()
+        } else {
+            // Long paths only say that they are long; read them up to
+            // their terminator.
+            let mut path = vec![0u8; flags.name_length as usize];
+            reader.read_exact(&mut path).map_err(|_| truncated())?;
+            if flags.name_length == NAME_MASK {
+                reader.read_until(0, &mut path).map_err(|_| truncated())?;
+                if path.pop() != Some(0) {
+                    return Err(truncated());
+                }
+                reader.set_position(reader.position() - 1);
+            }
 
+            // Skip the 1 - 8 nul bytes padding the entry to a multiple of 8.
+            let length = reader.position() - start;
+            let end = start + (length + 8) / 8 * 8;
+            if end > reader.get_ref().len() as u64 {
+                return Err(truncated());
+            }
+            reader.set_position(end);
+            path
+        };
 
-        let entry_one = IndexEntry { ctime_seconds: values[0], ctime_nanoseconds: values[1], mtime_seconds: values[2], mtime_nanoseconds: values[3], dev: values[4], ino: values[5], mode: values[6], uid: values[7], gid: values[8], filesize: values[9], sha, flags: u16::from_be_bytes(flags), path: String::from_utf8(path).unwrap() };
+        let path = String::from_utf8(path).map_err(|_| invalid("the path is not valid UTF-8".to_string()))?;
+        let entry_one = IndexEntry { ctime_seconds: values[0], ctime_nanoseconds: values[1], mtime_seconds: values[2], mtime_nanoseconds: values[3], dev: values[4], ino: values[5], mode: values[6], uid: values[7], gid: values[8], filesize: values[9], sha, flags, extended_flags, path };
         Ok(entry_one)
     }
 }
 
 #[derive(Clone)]
 pub struct IndexExtension {
-    signature: [u8; 4],
-    extension_size: u32,
-    extension_data: CacheTreeEntry
+    pub signature: [u8; 4],
+    pub extension_size: u32,
+    pub extension_data: CacheTreeEntry
 }
 
 impl IndexExtension {
@@ -264,7 +450,7 @@ impl IndexExtension {
         tree_cache.to_bytes(&mut cache_bytes);
 
         IndexExtension {
-            signature: [84, 82, 69, 69],
+            signature: TREE_SIGNATURE,
             extension_size: cache_bytes.len() as u32,
             extension_data: tree_cache
         }
@@ -289,29 +475,32 @@ impl Debug for IndexExtension {
          .finish()
     }
 }
-impl TryFrom<&mut Cursor<&[u8]>> for IndexExtension {
-    type Error = String;
-
-    fn try_from(reader: &mut Cursor<&[u8]>) -> Result<Self, Self::Error> {
+impl IndexExtension {
+    pub fn parse(reader: &mut Cursor<&[u8]>, format: ObjectFormat) -> Result<Self, IndexParseError> {
+        let offset = reader.position();
         let mut buffer = [0u8; 4];
         let _ = reader.read_exact(&mut buffer);
 
         let signature = buffer;
+        let invalid = |reason: String| IndexParseError::InvalidExtension { offset, signature, reason };
+        if signature != TREE_SIGNATURE {
+            return Err(invalid("only the TREE extension is understood".to_string()));
+        }
 
-        let _ = reader.read_exact(&mut buffer);
+        reader.read_exact(&mut buffer).map_err(|_| invalid("truncated extension header".to_string()))?;
         let extension_size = u32::from_be_bytes(buffer);
 
 
         let mut extension_data = vec![0u8; extension_size as usize];
-        let _ = reader.read_exact(&mut extension_data);
+        reader.read_exact(&mut extension_data).map_err(|_| invalid(format!("{} bytes of data run past the end of the index", extension_size)))?;
 
-        let cache_entry = CacheTreeEntry::try_from(extension_data).unwrap();
+        let cache_entry = CacheTreeEntry::parse(&extension_data, format).map_err(invalid)?;
 
         Ok(IndexExtension { signature, extension_size, extension_data: cache_entry})
     }
 }
 
-fn build_tree(path: &str, path_map: &HashMap<String, Vec<IndexEntry>>) -> Result<CacheTreeEntry, String> {
+fn build_tree(path: &str, path_map: &HashMap<String, Vec<IndexEntry>>, store: &ObjectStore) -> Result<CacheTreeEntry, String> {
     // Get all subdirectories of the current path
     let mut subdirs = HashSet::new();
     
@@ -342,8 +531,8 @@ fn build_tree(path: &str, path_map: &HashMap<String, Vec<IndexEntry>>) -> Result
     
     // Convert HashSet to Vec for sorting
     let mut subdirs_vec: Vec<String> = subdirs.into_iter().collect();
-    // Sort the subdirectories lexicographically
-    subdirs_vec.sort();
+    // Git keeps cache-tree subtrees ordered by name length, then by name.
+    subdirs_vec.sort_by(|a, b| a.len().cmp(&b.len()).then(a.cmp(b)));
     
     for subdir in subdirs_vec {
         let subdir_path = if path.is_empty() {
@@ -352,7 +541,7 @@ fn build_tree(path: &str, path_map: &HashMap<String, Vec<IndexEntry>>) -> Result
             format!("{}/{}", path, subdir)
         };
         
-        let subtree = build_tree(&subdir_path, path_map)?;
+        let subtree = build_tree(&subdir_path, path_map, store)?;
         total_entry_count += subtree.entry_count as usize;
         subtrees.push(subtree);
     }
@@ -363,8 +552,8 @@ fn build_tree(path: &str, path_map: &HashMap<String, Vec<IndexEntry>>) -> Result
     // Sort file entries before adding them
     let mut sorted_entries = current_entries.clone();
     sorted_entries.sort_by(|a, b| {
-        let a_name = a.path.split('/').last().unwrap_or(&a.path);
-        let b_name = b.path.split('/').last().unwrap_or(&b.path);
+        let a_name = a.path.split('/').next_back().unwrap_or(&a.path);
+        let b_name = b.path.split('/').next_back().unwrap_or(&b.path);
         a_name.cmp(b_name)
     });
 
@@ -372,7 +561,7 @@ fn build_tree(path: &str, path_map: &HashMap<String, Vec<IndexEntry>>) -> Result
 
     // This is for the entries in a directory.
     for entry in &sorted_entries {
-        let filename = entry.path.split("/").last().unwrap_or(&entry.path);
+        let filename = entry.path.split('/').next_back().unwrap_or(&entry.path);
 
         let mut byte_content: Vec<u8> = Vec::new();
 
@@ -386,7 +575,7 @@ fn build_tree(path: &str, path_map: &HashMap<String, Vec<IndexEntry>>) -> Result
         byte_content.extend_from_slice(space);
         byte_content.extend_from_slice(filename_bytes);
         byte_content.extend_from_slice(null_bytes);
-        byte_content.extend_from_slice(&entry.sha);
+        byte_content.extend_from_slice(entry.sha.as_bytes());
 
         tree_map.insert(filename, byte_content);
     }
@@ -406,7 +595,7 @@ fn build_tree(path: &str, path_map: &HashMap<String, Vec<IndexEntry>>) -> Result
             .map_err(|_| "Invalid UTF-8 in path".to_string())?;
         
 
-        let dirname = path_str.split('/').last().unwrap_or(path_str).as_bytes();
+        let dirname = path_str.split('/').next_back().unwrap_or(path_str).as_bytes();
 
         let mut byte_content = Vec::new();
         let mode_bytes = b"40000";
@@ -415,7 +604,7 @@ fn build_tree(path: &str, path_map: &HashMap<String, Vec<IndexEntry>>) -> Result
         byte_content.extend_from_slice(b" ");
         byte_content.extend_from_slice(dirname);
         byte_content.extend_from_slice(b"\0");
-        byte_content.extend_from_slice(&subtree.sha);
+        byte_content.extend_from_slice(subtree.sha.as_bytes());
         
         tree_map.insert(std::str::from_utf8(dirname).expect("Invald UTF-8"), byte_content);
     }
@@ -426,18 +615,8 @@ fn build_tree(path: &str, path_map: &HashMap<String, Vec<IndexEntry>>) -> Result
 
     vec_map.iter().for_each(|(_, y)| tree_content.extend_from_slice(y));
     
-    // Hash the tree content
-    //TODO: Create a variable representing the entire tree object.
-    let header = format!("tree {}", tree_content.len());
-    let mut hasher = Sha1::new();
-    hasher.update(header.as_bytes()); 
-    hasher.update(&[0]);
-    hasher.update(&tree_content);
-
-    let sha = hasher.finalize();
-    
-    let mut sha_array = [0u8; 20];
-    sha_array.copy_from_slice(&sha);
+    // Write the tree to an object file.
+    let tree_oid = store.write(ObjectKind::Tree, &tree_content).map_err(|e| e.to_string())?;
     
     // Get just the directory name for the path - not the full path
     let dirname = if path.is_empty() {
@@ -445,46 +624,30 @@ fn build_tree(path: &str, path_map: &HashMap<String, Vec<IndexEntry>>) -> Result
         vec![0]
     } else {
         // For subdirectories, use just the last component of the path
-        let last_component = path.split('/').last().unwrap_or(path);
+        let last_component = path.split('/').next_back().unwrap_or(path);
         let mut path_bytes = last_component.as_bytes().to_vec();
         path_bytes.push(0); // Add null terminator
         path_bytes
     };
 
-    // Write the tree to an object file.
-    let mut encoded_tree_object = ZlibEncoder::new(Vec::new(), Compression::default());
-    encoded_tree_object.write_all(&[header.as_bytes(), &[0], &tree_content].concat()).expect("Error writing to compressing stream");
-    let compressed_bytes = encoded_tree_object.finish().expect("Error compressing tree content");
-
-    let mut root = env::current_dir().expect("Unable to get cwd");
-    root.push(".warp");
-    root.push("objects");
-
-    let sha_hex = hex::encode(&sha_array);
-    let (dir_hash, file_hash) = sha_hex.split_at(2);
-    crate::auxiliary::push_dir_with_file(root.clone(), dir_hash, file_hash);
-
-    root.push(dir_hash);
-    root.push(file_hash);
-
-    let mut compressed_file = fs::File::create(root).expect("Unable to create file");
-    compressed_file.write(&compressed_bytes).expect("Error writing to file");
-    
     // Create and return the CacheTreeEntry
     Ok(CacheTreeEntry {
         path: dirname,
-        entry_count: total_entry_count.min(u8::MAX as usize) as u8,
-        subtree_count: subtrees.len().min(u8::MAX as usize) as u8,
-        sha: sha_array,
+        entry_count: total_entry_count as i32,
+        subtree_count: subtrees.len() as u32,
+        sha: tree_oid,
         subtrees: if subtrees.is_empty() { None } else { Some(subtrees) },
     })
 }
+// A directory in the TREE extension. Counts are written as ASCII decimals;
+// an entry count of -1 marks a directory whose entries changed since its
+// tree was written, and such a node has no tree id on disk.
 #[derive(Clone)]
 pub struct CacheTreeEntry {
     pub path: Vec<u8>,
-    pub entry_count: u8,
-    pub subtree_count: u8,
-    pub sha: [u8; 20],
+    pub entry_count: i32,
+    pub subtree_count: u32,
+    pub sha: ObjectId,
     pub subtrees: Option<Vec<CacheTreeEntry>>
 }
 
@@ -495,6 +658,9 @@ impl TryFrom<Vec<IndexEntry>> for CacheTreeEntry {
         if entries.is_empty() {
             return Err("Cannot create a CacheEntry from an empty list of entrues".to_string());
         }
+        if let Some(entry) = entries.iter().find(|entry| entry.stage() != 0) {
+            return Err(format!("{}: unmerged (stage {}), resolve the conflicts before writing a tree", entry.path, entry.stage()));
+        }
 
         let mut path_map: HashMap<String, Vec<IndexEntry>> = HashMap::new();
         println!("{:?}", entries);
@@ -505,19 +671,20 @@ impl TryFrom<Vec<IndexEntry>> for CacheTreeEntry {
 
             if components.len() == 1 {
                 path_map.entry("".to_string())
-                    .or_insert_with(Vec::new)
+                    .or_default()
                     .push(entry);
             } else {
                 let dir = components[..components.len() - 1].join("/");
                 path_map.entry(dir)
-                    .or_insert_with(Vec::new)
+                    .or_default()
                     .push(entry);
             }
         }
 
         println!("PATH_MAP: {:#?}", path_map);
         // Err("I will implement it later!".to_string())
-        build_tree("", &path_map)
+        let store = ObjectStore::open().map_err(|e| e.to_string())?;
+        build_tree("", &path_map, &store)
     }
 }
 
@@ -529,13 +696,12 @@ impl CacheTreeEntry {
         // let mut bytes = Vec::new();
 
         bytes.extend(&self.path);
-        bytes.extend(&(self.entry_count + 48).to_be_bytes());
-        bytes.extend((32 as u8).to_be_bytes());
-        bytes.extend(&(self.subtree_count + 48).to_be_bytes());
-        bytes.extend((10 as u8).to_be_bytes());
-        bytes.extend(&self.sha);
+        bytes.extend(format!("{} {}\n", self.entry_count, self.subtree_count).as_bytes());
+        if self.is_valid() {
+            bytes.extend(self.sha.as_bytes());
+        }
 
-        if let None = self.subtrees {
+        if self.subtrees.is_none() {
             return bytes.to_vec();
         } else if let Some(subtrees) = &self.subtrees {
             for subtree in subtrees {
@@ -546,96 +712,142 @@ impl CacheTreeEntry {
     }
 }
 
+impl CacheTreeEntry {
+    pub fn is_valid(&self) -> bool {
+        self.entry_count >= 0
+    }
+
+    // The directory name, without the terminator.
+    pub fn name(&self) -> &[u8] {
+        self.path.strip_suffix(&[0]).unwrap_or(&self.path)
+    }
+
+    // Mark the directories leading to `path`, relative to this node, as
+    // changed. Trees of other directories stay valid.
+    pub fn invalidate(&mut self, path: &str) {
+        self.entry_count = -1;
+        let Some((dir, rest)) = path.split_once('/') else {
+            return;
+        };
+        if let Some(subtree) = self.subtrees.iter_mut().flatten().find(|subtree| subtree.name() == dir.as_bytes()) {
+            subtree.invalidate(rest);
+        }
+    }
+}
+
 impl Debug for CacheTreeEntry {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("CacheTreeEntry")
          .field("path", &self.path)
          .field("entry_count", &self.entry_count)
          .field("subtree_count", &self.subtree_count)
-         .field("sha", &self.sha.iter().map(|ch| format!("{:02x}", ch)).collect::<String>())
+         .field("sha", &self.sha.to_hex())
          .field("subtrees", &self.subtrees)
          .finish()
     }
 }
 
-fn create_cache(reader: &mut BufReader<&[u8]>) -> CacheTreeEntry {
-    let mut single_byte = [0u8; 1];
-    let mut path = String::new();
-    // Nul terminated path component
-    while let Ok(_) = reader.read_exact(&mut single_byte) {
-        if single_byte[0] == 0 {
-            break;
-        } else {
-            path.push(single_byte[0].into());
-        }
+// Read up to `delimiter` and parse what came before it as a decimal.
+fn read_decimal<T: std::str::FromStr>(reader: &mut BufReader<&[u8]>, delimiter: u8) -> Result<T, String> {
+    let mut digits = Vec::new();
+    reader.read_until(delimiter, &mut digits).map_err(|e| e.to_string())?;
+    if digits.pop() != Some(delimiter) {
+        return Err("truncated cache-tree count".to_string());
     }
+    std::str::from_utf8(&digits).ok().and_then(|digits| digits.parse().ok())
+        .ok_or_else(|| format!("invalid cache-tree count '{}'", String::from_utf8_lossy(&digits)))
+}
 
-    let new_path = CString::new(path).unwrap();
-    let x = new_path.as_bytes_with_nul().to_owned();
-
-    // ASCII Entry count
-    reader.read_exact(&mut single_byte);
-    let entry_count = u8::from_be_bytes(single_byte) - 48;
-
-    // ASCII Space
-    reader.read_exact(&mut single_byte);
+fn create_cache(reader: &mut BufReader<&[u8]>, format: ObjectFormat) -> Result<CacheTreeEntry, String> {
+    // Nul terminated path component, kept with its terminator.
+    let mut x = Vec::new();
+    reader.read_until(0, &mut x).map_err(|e| e.to_string())?;
+    if x.last() != Some(&0) {
+        return Err("truncated cache-tree path".to_string());
+    }
 
-    // ASCII number of subtrees
-    reader.read_exact(&mut single_byte);
-    let subtree_count = u8::from_be_bytes(single_byte) - 48;
-    
-    let subtrees: Option<Vec<CacheTreeEntry>>;
+    // ASCII entry count (-1 when invalidated), a space, the ASCII number of
+    // subtrees and a newline.
+    let entry_count: i32 = read_decimal(reader, b' ')?;
+    let subtree_count: u32 = read_decimal(reader, b'\n')?;
+    if entry_count < -1 {
+        return Err(format!("invalid cache-tree entry count {}", entry_count));
+    }
 
-    // ASCII newline
-    reader.read_exact(&mut single_byte);
-    let mut sha = [0u8; 20];
-    
-    // SHA tree object
-    reader.read_exact(&mut sha);
+    // SHA tree object, only present for valid nodes.
+    let sha = if entry_count >= 0 {
+        let mut sha = vec![0u8; format.raw_len()];
+        reader.read_exact(&mut sha).map_err(|_| "truncated cache-tree object id".to_string())?;
+        ObjectId::from_slice(&sha).ok_or("invalid cache-tree object id")?
+    } else {
+        ObjectId::null(format)
+    };
 
-    if subtree_count > 0 {
+    let subtrees = if subtree_count > 0 {
         let mut trees: Vec<CacheTreeEntry> = Vec::new();
         for _ in 0..subtree_count {
-            trees.push(create_cache(reader));
+            trees.push(create_cache(reader, format)?);
         }
 
-        subtrees = Some(trees);
+        Some(trees)
     } else {
-        subtrees = None;
-    }
-    
+        None
+    };
 
-    return CacheTreeEntry { path: x, entry_count, subtree_count, sha, subtrees };
+    Ok(CacheTreeEntry { path: x, entry_count, subtree_count, sha, subtrees })
 }
 
 
-impl TryFrom<Vec<u8>> for CacheTreeEntry {
-    type Error = String;
-
-    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
-        let mut reader = BufReader::new(value.as_slice());
-        Ok(create_cache(&mut reader))
+impl CacheTreeEntry {
+    pub fn parse(bytes: &[u8], format: ObjectFormat) -> Result<Self, String> {
+        let mut reader = BufReader::new(bytes);
+        create_cache(&mut reader, format)
     }
 }
 
-#[derive(Debug)]
+// Why an index file could not be read, with the byte offset at which
+// reading failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum IndexParseError {
-    InvalidSignature,
-    UnsuppoertedVersion(u32),
-    ChecksumMismatch,
-    InvalidEntry,
-    Io,
-    InvalidExtension
+    InvalidSignature { offset: u64, found: [u8; 4] },
+    UnsupportedVersion { offset: u64, version: u32 },
+    // The file ends before its header or checksum.
+    Truncated { offset: u64 },
+    TruncatedEntry { offset: u64 },
+    InvalidEntry { offset: u64, reason: String },
+    InvalidExtension { offset: u64, signature: [u8; 4], reason: String },
+    ChecksumMismatch { offset: u64, expected: ObjectId, actual: ObjectId }
+}
+
+impl fmt::Display for IndexParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IndexParseError::InvalidSignature { offset, found } => write!(f, "bad signature '{}' at offset {}, this is not an index file", String::from_utf8_lossy(found).escape_debug(), offset),
+            IndexParseError::UnsupportedVersion { offset, version } => write!(f, "unsupported index version {} at offset {} (versions 2, 3 and 4 are supported)", version, offset),
+            IndexParseError::Truncated { offset } => write!(f, "the index ends early, at offset {}", offset),
+            IndexParseError::TruncatedEntry { offset } => write!(f, "the entry at offset {} is truncated", offset),
+            IndexParseError::InvalidEntry { offset, reason } => write!(f, "the entry at offset {} is invalid: {}", offset, reason),
+            IndexParseError::InvalidExtension { offset, signature, reason } => write!(f, "bad '{}' extension at offset {}: {}", String::from_utf8_lossy(signature).escape_debug(), offset, reason),
+            IndexParseError::ChecksumMismatch { offset, expected, actual } => write!(f, "checksum mismatch at offset {}: the index records {} but its content hashes to {}", offset, expected, actual),
+        }
+    }
+}
+
+impl std::error::Error for IndexParseError {}
+
+// The error to report for an index file that cannot be parsed.
+fn corrupt_index(path: &Path, error: IndexParseError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{} is corrupt: {}. Remove it and run `update-index --add` to rebuild it", path.display(), error))
 }
 
 pub struct WarpIndex {
     pub header: IndexHeader,
     pub entries: Vec<IndexEntry>,
     pub extensions: Option<IndexExtension>,
-    pub checksum: [u8; 20]
+    pub checksum: ObjectId
 }
 
-fn generic_index() -> PathBuf{
+pub fn generic_index() -> PathBuf{
     let mut root = std::env::current_dir().expect("Unable to get the current working directory");
     root.push(".warp");
     root.push("index");
@@ -648,41 +860,159 @@ pub fn index_file_exists() -> bool {
     root.is_file()
 }
 
+// Parse the index file if the repository has one.
+pub fn read_index() -> std::io::Result<Option<WarpIndex>> {
+    if !index_file_exists() {
+        return Ok(None);
+    }
+
+    let format = ObjectStore::open()?.format();
+    let buffer = fs::read(generic_index())?;
+    WarpIndex::parse(&mut Cursor::new(buffer.as_slice()), format)
+        .map(Some)
+        .map_err(|e| corrupt_index(&generic_index(), e))
+}
+
 pub fn create_new_index() {
     let root = generic_index();
     fs::File::create(root).expect("Unable to create a index file");
 }
 impl WarpIndex {
-    pub fn without_extension(entries: Vec<IndexEntry>) -> Self {
-        let new_index_header = IndexHeader::new([68, 73, 82, 67], 2, entries.len() as u32);
-        let mut hasher = Sha1::new();
+    // A new index is written as version 2 unless an entry needs the
+    // extended flags of version 3.
+    pub fn without_extension(entries: Vec<IndexEntry>, format: ObjectFormat) -> Self {
+        let version = minimum_version(&entries);
+        let new_index_header = IndexHeader::new(INDEX_SIGNATURE, version, entries.len() as u32);
+        let mut index = WarpIndex { header: new_index_header, entries, extensions: None, checksum: ObjectId::null(format) };
+        index.update_checksum();
+        index
+    }
 
-        let mut bytes = Vec::new();
-        bytes.extend(new_index_header.to_bytes());
-        entries.iter().for_each(|entry| bytes.extend(entry.to_bytes()));
-        hasher.update(bytes);
-        let mut checksum = [0u8; 20];
+    // Change the on-disk format version; the entries and extensions are
+    // unchanged.
+    pub fn set_version(&mut self, version: u32) -> io::Result<()> {
+        if !SUPPORTED_VERSIONS.contains(&version) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("index version {} is not supported (use 2, 3 or 4)", version)));
+        }
+        if version < minimum_version(&self.entries) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("index version {} cannot record intent-to-add or skip-worktree entries", version)));
+        }
+        self.header.version = version;
+        self.update_checksum();
+        Ok(())
+    }
+
+    pub fn has_conflicts(&self) -> bool {
+        self.entries.iter().any(|entry| entry.stage() != 0)
+    }
+
+    // Add an entry, replacing the one at the same path and stage. A merged
+    // (stage 0) entry resolves its path and drops the conflict stages; a
+    // conflict stage replaces the merged entry.
+    pub fn add_entry(&mut self, entry: IndexEntry) {
+        self.invalidate_path(&entry.path);
+        self.entries.retain(|existing| {
+            existing.path != entry.path || (existing.stage() != entry.stage() && existing.stage() != 0 && entry.stage() != 0)
+        });
+        let position = self.entries.partition_point(|existing| entry_order(existing, &entry) == Ordering::Less);
+        self.entries.insert(position, entry);
+        self.header.entry_count = self.entries.len() as u32;
+        self.update_checksum();
+    }
+
+    pub fn remove_path(&mut self, path: &str) {
+        self.invalidate_path(path);
+        self.entries.retain(|entry| entry.path != path);
+        self.header.entry_count = self.entries.len() as u32;
+        self.update_checksum();
+    }
+
+    // Invalidate the cache-tree nodes of the directories containing `path`,
+    // keeping the trees of untouched directories for the next write-tree.
+    pub fn invalidate_path(&mut self, path: &str) {
+        if let Some(extension) = self.extensions.take() {
+            let mut cache_tree = extension.extension_data;
+            cache_tree.invalidate(path);
+            self.extensions = Some(IndexExtension::from_cache(cache_tree));
+        }
+    }
 
-        let bytes = hasher.finalize();
-        checksum.copy_from_slice(bytes.as_slice());
+    // Apply `git update-index --index-info` lines: `<mode> <sha> <stage>\t<path>`
+    // (or without the stage, for stage 0). Mode 0 removes the path.
+    pub fn update_index_info<R: BufRead>(reader: R) -> io::Result<usize> {
+        let store = ObjectStore::open()?;
+        let mut warp_index = match read_index()? {
+            Some(warp_index) => warp_index,
+            None => WarpIndex::without_extension(Vec::new(), store.format()),
+        };
+
+        let mut count = 0;
+        for line in reader.lines() {
+            let line = line?;
+            let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("malformed index info line '{}'", line));
+            let (info, path) = line.split_once('\t').ok_or_else(invalid)?;
+            let fields: Vec<&str> = info.split(' ').collect();
+            let (mode, sha, stage) = match fields[..] {
+                [mode, sha] => (mode, sha, "0"),
+                [mode, sha, stage] => (mode, sha, stage),
+                _ => return Err(invalid()),
+            };
+            let mode = u32::from_str_radix(mode, 8).map_err(|_| invalid())?;
+            let stage: u8 = stage.parse().ok().filter(|stage| *stage <= 3).ok_or_else(invalid)?;
+
+            if mode == 0 {
+                warp_index.remove_path(path);
+            } else {
+                let sha: ObjectId = sha.parse().map_err(|_| invalid())?;
+                // Gitlinks name commits of another repository.
+                if mode != 0o160000 && !store.contains(&sha) {
+                    return Err(io::Error::new(io::ErrorKind::NotFound, format!("{}: object {} does not exist", path, sha)));
+                }
+                warp_index.add_entry(IndexEntry::staged(path.to_string(), mode, sha, stage));
+            }
+            count += 1;
+        }
 
-        WarpIndex { header: new_index_header, entries, extensions: None, checksum }
+        fs::write(generic_index(), warp_index.to_bytes())?;
+        Ok(count)
+    }
+
+    // Rewrite the repository's index in another format version.
+    pub fn convert_version(version: u32) -> io::Result<()> {
+        let Some(mut warp_index) = read_index()? else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "there is no index to convert"));
+        };
+        warp_index.set_version(version)?;
+        fs::write(generic_index(), warp_index.to_bytes())
+    }
+
+    // The trailing checksum covers everything before it and is as wide as
+    // the repository's object ids.
+    pub fn update_checksum(&mut self) {
+        let bytes = self.to_bytes();
+        let content = &bytes[..bytes.len() - self.checksum.as_bytes().len()];
+        self.checksum = self.checksum.format().digest(content);
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
         let index_header_bytes = self.header.to_bytes();
-        let index_entry_bytes = self.entries.iter().map(|entry| entry.to_bytes()).collect::<Vec<_>>().concat();
+        let mut index_entry_bytes = Vec::new();
+        let mut previous_path = "";
+        for entry in &self.entries {
+            index_entry_bytes.extend(entry.encode(self.header.version, previous_path));
+            previous_path = &entry.path;
+        }
         let extension_bytes = match &self.extensions {
             Some(ext) => ext.to_bytes(),
             None => Vec::new()
         };
         // let extension_bytes = self.extensions.as_ref().unwrap().to_bytes();
-        let checksum_bytes = self.checksum.to_vec();
+        let checksum_bytes = self.checksum.as_bytes().to_vec();
 
         [index_header_bytes, index_entry_bytes, extension_bytes, checksum_bytes].concat()
     }
 
-    pub fn write_tree() {
+    pub fn write_tree() -> io::Result<()> {
         // We create an index from the file
         let mut index_path = std::env::current_dir().unwrap(); //TODO : Traverse up the tree and find .warp file instead of this: Err!
         index_path.push(".warp");
@@ -690,20 +1020,23 @@ impl WarpIndex {
 
         // Read the index to a buffer
         let mut buffer = Vec::new();
-        let _ = fs::File::open(&index_path).unwrap().read_to_end(&mut buffer);
+        fs::File::open(&index_path)?.read_to_end(&mut buffer)?;
 
         // Create a WarpIndex from the index fd
         // We create the Cursor from the buffer which holds the index items.
         // The cursor will be used to build different parts of the WarpIndex.
         // TODO: A builder can be used here instead of try_from in the future!
-        let mut warp_index = WarpIndex::try_from(&mut Cursor::new(buffer.as_slice())).unwrap();
+        let format = ObjectStore::open()?.format();
+        let mut warp_index = WarpIndex::parse(&mut Cursor::new(buffer.as_slice()), format).map_err(|e| corrupt_index(&index_path, e))?;
         
         // Create a CacheEntry from the index entries file.
-        let tree_cache = CacheTreeEntry::try_from(warp_index.entries.clone()).unwrap();
+        let tree_cache = CacheTreeEntry::try_from(warp_index.entries.clone())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
 
         let extension = IndexExtension::from_cache(tree_cache);
 
         warp_index.extensions = Some(extension);
+        warp_index.update_checksum();
         // Create the extension struct now
 
         // Get the current index binary file
@@ -712,25 +1045,25 @@ impl WarpIndex {
         index_path.push("index");
 
         // Write to file.
-        fs::OpenOptions::new().write(true).open(&index_path).unwrap().write_all(&warp_index.to_bytes()).expect("Unable to write to index file");
+        fs::OpenOptions::new().write(true).truncate(true).open(&index_path).unwrap().write_all(&warp_index.to_bytes()).expect("Unable to write to index file");
+        Ok(())
     }
 
-    pub fn update_index(paths: Vec<PathBuf>) {
+    pub fn update_index(paths: Vec<PathBuf>) -> io::Result<()> {
+        let format = ObjectStore::open()?.format();
         if !index_file_exists() {
             // Create an index file.
             create_new_index(); // Todo : Proper Error Handling later on!!
             let index = generic_index();
             
-            // Create a vector of IndexEntries from the paths.
-            let mut index_entries = Vec::new();
-            for file in paths {
+            // Create a vector of IndexEntries from the paths, sorted by path.
+            for file in &paths {
                 println!("FILE: {:?}", file);
-                let index_entry = IndexEntry::entry_from_file(file);
-                index_entries.push(index_entry);
             }
+            let index_entries = entries_from_paths(paths);
             
             // Create a WarpIndex from index_entries and write it to the index file.
-            fs::OpenOptions::new().write(true).open(index).unwrap().write_all(&WarpIndex::without_extension(index_entries).to_bytes()).expect("Unable to write to the index file");
+            fs::OpenOptions::new().write(true).open(index).unwrap().write_all(&WarpIndex::without_extension(index_entries, format).to_bytes()).expect("Unable to write to the index file");
         } else {
             // Get the current index binary file
             let mut index_path = std::env::current_dir().unwrap();
@@ -738,32 +1071,54 @@ impl WarpIndex {
             index_path.push("index");
 
             // Open it.
-            let mut index = fs::File::open(&index_path).unwrap();
+            let mut index = fs::File::open(&index_path)?;
 
             // Read it into this buffer
             let mut buffer = Vec::new();
-            let _ = index.read_to_end(&mut buffer).unwrap();
+            index.read_to_end(&mut buffer)?;
 
             // Create a WarpIndex from the index file.
-            let warp_index = WarpIndex::try_from(&mut Cursor::new(buffer.as_slice())).unwrap();
-
-            // Create entries from the the paths passed in the function
-            let mut index_entries = Vec::new();
-            for file in paths {
-                let index_entry = IndexEntry::entry_from_file(file);
-                index_entries.push(index_entry);
+            let mut warp_index = WarpIndex::parse(&mut Cursor::new(buffer.as_slice()), format).map_err(|e| corrupt_index(&index_path, e))?;
+
+            // Add entries for the paths passed in the function. A re-added
+            // path replaces its old entry (and any conflict stages), and the
+            // cache-tree loses only the directories of the added paths. The
+            // format version the index was written in is kept.
+            for entry in entries_from_paths(paths) {
+                warp_index.add_entry(entry);
             }
-
-            // Extend with the one from the index file. Sort before writing once again.
-            index_entries.extend_from_slice(&warp_index.entries);
-            index_entries.sort_by(|a, b| a.path.cmp(&b.path));
-
-            let new_warp_index = WarpIndex::without_extension(index_entries);
             
             // Write the bytes of this WarpIndex to the index file, we convert it to bytes format
-            fs::OpenOptions::new().write(true).open(&index_path).unwrap().write_all(&new_warp_index.to_bytes()).expect("Unable to write to the index file");
+            fs::OpenOptions::new().write(true).truncate(true).open(&index_path)?.write_all(&warp_index.to_bytes())?;
         }
 
+        Ok(())
+    }
+}
+
+// Build stage 0 entries for the given files. A path passed more than once is
+// added once, and the entries come back in index order.
+fn entries_from_paths(paths: Vec<PathBuf>) -> Vec<IndexEntry> {
+    let mut entries: Vec<IndexEntry> = Vec::new();
+    for file in paths {
+        let entry = IndexEntry::entry_from_file(file);
+        entries.retain(|existing| existing.path != entry.path);
+        entries.push(entry);
+    }
+    entries.sort_by(entry_order);
+    entries
+}
+
+// Entries are ordered by path, then by stage.
+fn entry_order(a: &IndexEntry, b: &IndexEntry) -> Ordering {
+    a.path.as_bytes().cmp(b.path.as_bytes()).then(a.stage().cmp(&b.stage()))
+}
+
+// The lowest version able to hold these entries.
+fn minimum_version(entries: &[IndexEntry]) -> u32 {
+    match entries.iter().any(|entry| entry.extended_flags != 0) {
+        true => 3,
+        false => 2,
     }
 }
 
@@ -773,36 +1128,67 @@ impl Debug for WarpIndex {
          .field("header", &self.header)
          .field("entries", &self.entries)
          .field("extensions", &self.extensions)
-         .field("checksum", &self.checksum.iter().map(|ch| format!("{:02x}", ch)).collect::<String>())
+         .field("checksum", &self.checksum.to_hex())
          .finish()
     }
 }
 
-impl TryFrom<&mut Cursor<&[u8]>> for WarpIndex {
-    type Error = IndexParseError;
+impl WarpIndex {
+    // Parse an index that starts at the reader's position and runs to the
+    // end of its buffer, checking the trailing checksum.
+    pub fn parse(reader: &mut Cursor<&[u8]>, format: ObjectFormat) -> Result<Self, IndexParseError> {
+        let data: &[u8] = reader.get_ref();
+        let start = reader.position() as usize;
 
-    fn try_from(reader: &mut Cursor<&[u8]>) -> Result<Self, Self::Error> {
-        let header = IndexHeader::try_from(&mut *reader).unwrap();
+        let header = IndexHeader::try_from(&mut *reader)?;
         let mut entries: Vec<IndexEntry> = Vec::new();
         for _ in 0..header.entry_count {
-
-            entries.push(IndexEntry::try_from(&mut *reader).unwrap());
+            let previous_path = entries.last().map_or("", |entry: &IndexEntry| entry.path.as_str());
+            let entry = IndexEntry::parse_version(&mut *reader, format, header.version, previous_path)?;
+            entries.push(entry);
         }
-        let mut signature = [0u8; 4];
-        let extensions;
-        reader.read_exact(&mut signature).unwrap();
-        if signature == [84, 82, 69, 69] {
-            reader.set_position(reader.position() - 4);
-            extensions = Some(IndexExtension::try_from(&mut *reader).unwrap());
-        } else {
-            reader.set_position(reader.position() - 4);
-            extensions = None;
+
+        // Extensions fill the space between the entries and the checksum.
+        let trailer = data.len().checked_sub(format.raw_len())
+            .filter(|trailer| *trailer as u64 >= reader.position())
+            .ok_or(IndexParseError::Truncated { offset: data.len() as u64 })?;
+
+        let mut extensions = None;
+        while (reader.position() as usize) < trailer {
+            let offset = reader.position() as usize;
+            if trailer - offset < 8 {
+                return Err(IndexParseError::InvalidExtension { offset: offset as u64, signature: [0; 4], reason: "truncated extension header".to_string() });
+            }
+            let mut signature = [0u8; 4];
+            signature.copy_from_slice(&data[offset..offset + 4]);
+            let size = u32::from_be_bytes([data[offset + 4], data[offset + 5], data[offset + 6], data[offset + 7]]) as usize;
+            if size > trailer - offset - 8 {
+                return Err(IndexParseError::InvalidExtension { offset: offset as u64, signature, reason: format!("{} bytes of data run into the checksum", size) });
+            }
+
+            // Extensions we do not know are optional when their signature
+            // starts with an uppercase letter; they are dropped on rewrite.
+            if signature == TREE_SIGNATURE {
+                let extension = IndexExtension::parse(&mut *reader, format)?;
+                reader.set_position((offset + 8 + size) as u64);
+                extensions = Some(extension);
+            } else if signature[0].is_ascii_uppercase() {
+                reader.set_position((offset + 8 + size) as u64);
+            } else {
+                return Err(IndexParseError::InvalidExtension { offset: offset as u64, signature, reason: "this required extension is not supported".to_string() });
+            }
         }
-        // Read the next four bytes here. If it is the signature tree, we return some, 
-        // otherwise extensions is none.
 
-        let mut checksum = [0u8; 20];
-        reader.read_exact(&mut checksum).unwrap();
+        // An all-zero checksum means the writer skipped hashing, as git
+        // does with `index.skipHash`.
+        let checksum = ObjectId::from_slice(&data[trailer..]).ok_or(IndexParseError::Truncated { offset: trailer as u64 })?;
+        if checksum != ObjectId::null(format) {
+            let actual = format.digest(&data[start..trailer]);
+            if actual != checksum {
+                return Err(IndexParseError::ChecksumMismatch { offset: trailer as u64, expected: checksum, actual });
+            }
+        }
+        reader.set_position(data.len() as u64);
 
         Ok(WarpIndex {
             header,
@@ -830,27 +1216,179 @@ mod tests {
     fn test_index_entries() {
         let mut reader = Cursor::new(INDEX_DATA);
         let _ = IndexHeader::try_from(&mut reader).unwrap();
-        let test_entry = IndexEntry::try_from(&mut reader).unwrap();
+        let test_entry = IndexEntry::parse(&mut reader, ObjectFormat::Sha1).unwrap();
 
         assert_eq!(test_entry.path, "filea.txt".to_string());
-        assert_eq!(test_entry.sha.iter().map(|ch| format!("{:02x}", ch)).collect::<String>(), "77ef3bbc6c333c6088eba7a7b0c4c26203ed9765".to_string());
+        assert_eq!(test_entry.sha.to_hex(), "77ef3bbc6c333c6088eba7a7b0c4c26203ed9765".to_string());
     }
 
     #[test]
     fn test_warp_index() {
         let mut reader =  Cursor::new(INDEX_DATA);
-        let warp_index = WarpIndex::try_from(&mut reader).unwrap();
+        let warp_index = WarpIndex::parse(&mut reader, ObjectFormat::Sha1).unwrap();
 
-        assert_eq!(warp_index.checksum, hex!("53b5bfc28367bc8aaf62e76f76ffa5567fcd2fec"));
+        assert_eq!(warp_index.checksum.as_bytes(), hex!("53b5bfc28367bc8aaf62e76f76ffa5567fcd2fec"));
     }
 
     #[test]
     fn test_warp_to_bytes() {
         let mut reader = Cursor::new(INDEX_DATA);
-        let warp_index = WarpIndex::try_from(&mut reader).unwrap();
+        let warp_index = WarpIndex::parse(&mut reader, ObjectFormat::Sha1).unwrap();
 
         let warp_bytes = warp_index.to_bytes();
 
         assert_eq!(warp_bytes, INDEX_DATA);
     }
+
+    #[test]
+    fn test_sha256_index_round_trip() {
+        let mut entry = WarpIndex::parse(&mut Cursor::new(NO_TREE), ObjectFormat::Sha1).unwrap().entries[0].clone();
+        entry.sha = ObjectFormat::Sha256.hash_object(ObjectKind::Blob, b"hello\n");
+
+        let warp_index = WarpIndex::without_extension(vec![entry.clone()], ObjectFormat::Sha256);
+        let bytes = warp_index.to_bytes();
+        assert_eq!(warp_index.checksum, ObjectFormat::Sha256.digest(&bytes[..bytes.len() - 32]));
+
+        let parsed = WarpIndex::parse(&mut Cursor::new(bytes.as_slice()), ObjectFormat::Sha256).unwrap();
+        assert_eq!(parsed.entries[0].sha, entry.sha);
+        assert_eq!(parsed.checksum, warp_index.checksum);
+    }
+
+    #[test]
+    fn test_index_versions_round_trip() {
+        let mut warp_index = WarpIndex::parse(&mut Cursor::new(INDEX_DATA), ObjectFormat::Sha1).unwrap();
+        let v2_bytes = warp_index.to_bytes();
+        assert!(warp_index.set_version(5).is_err());
+
+        warp_index.set_version(4).unwrap();
+        let v4_bytes = warp_index.to_bytes();
+        assert!(v4_bytes.len() < v2_bytes.len());
+        let mut parsed = WarpIndex::parse(&mut Cursor::new(v4_bytes.as_slice()), ObjectFormat::Sha1).unwrap();
+        assert_eq!(parsed.header.version, 4);
+        assert_eq!(parsed.entries.iter().map(|entry| entry.path.as_str()).collect::<Vec<_>>(),
+                   ["filea.txt", "fileb.txt", "src/db/postgres.txt", "src/filec.txt", "zed/filed.txt"]);
+        assert_eq!(parsed.to_bytes(), v4_bytes);
+
+        parsed.entries[1].extended_flags = SKIP_WORKTREE;
+        assert!(parsed.set_version(2).is_err());
+        parsed.set_version(3).unwrap();
+        let v3_bytes = parsed.to_bytes();
+        let reparsed = WarpIndex::parse(&mut Cursor::new(v3_bytes.as_slice()), ObjectFormat::Sha1).unwrap();
+        assert_eq!(reparsed.entries[1].extended_flags, SKIP_WORKTREE);
+        assert_eq!(reparsed.entries[2].path, "src/db/postgres.txt");
+
+        let mut back = WarpIndex::parse(&mut Cursor::new(v4_bytes.as_slice()), ObjectFormat::Sha1).unwrap();
+        back.set_version(2).unwrap();
+        assert_eq!(back.to_bytes(), v2_bytes);
+    }
+
+    #[test]
+    fn test_entry_flags_and_long_paths() {
+        let flags = EntryFlags { assume_valid: true, extended: false, stage: 2, name_length: 9 };
+        assert_eq!(flags.to_bits(), 0xa009);
+        assert_eq!(EntryFlags::from_bits(0xa009), flags);
+        assert_eq!(EntryFlags::for_path(&"x".repeat(5000)).name_length, NAME_MASK);
+
+        let mut warp_index = WarpIndex::parse(&mut Cursor::new(INDEX_DATA), ObjectFormat::Sha1).unwrap();
+        warp_index.entries[4].path = format!("zed/{}", "d".repeat(5000));
+        warp_index.entries[4].flags.assume_valid = true;
+        for version in SUPPORTED_VERSIONS {
+            warp_index.set_version(version).unwrap();
+            let bytes = warp_index.to_bytes();
+            let parsed = WarpIndex::parse(&mut Cursor::new(bytes.as_slice()), ObjectFormat::Sha1).unwrap();
+            assert_eq!(parsed.entries[4].path, warp_index.entries[4].path);
+            assert_eq!(parsed.entries[4].flags, EntryFlags { assume_valid: true, ..EntryFlags::for_path(&parsed.entries[4].path) });
+            assert_eq!(parsed.checksum, warp_index.checksum);
+        }
+    }
+
+    #[test]
+    fn test_conflict_stages() {
+        let mut warp_index = WarpIndex::parse(&mut Cursor::new(NO_TREE), ObjectFormat::Sha1).unwrap();
+        let sha = warp_index.entries[1].sha;
+        for stage in [3, 1, 2] {
+            warp_index.add_entry(IndexEntry::staged("fileb.txt".to_string(), 0o100644, sha, stage));
+        }
+        warp_index.add_entry(IndexEntry::staged("fileb.txt".to_string(), 0o100644, sha, 2));
+        assert!(warp_index.has_conflicts());
+        assert_eq!(warp_index.entries.iter().map(|entry| (entry.path.as_str(), entry.stage())).collect::<Vec<_>>(),
+                   [("filea.txt", 0), ("fileb.txt", 1), ("fileb.txt", 2), ("fileb.txt", 3), ("src/filec.txt", 0)]);
+
+        let bytes = warp_index.to_bytes();
+        let parsed = WarpIndex::parse(&mut Cursor::new(bytes.as_slice()), ObjectFormat::Sha1).unwrap();
+        assert_eq!(parsed.entries[3].stage(), 3);
+        assert_eq!(parsed.header.entry_count, 5);
+        assert!(CacheTreeEntry::try_from(parsed.entries).unwrap_err().contains("unmerged"));
+
+        warp_index.add_entry(IndexEntry::staged("fileb.txt".to_string(), 0o100644, sha, 0));
+        assert!(!warp_index.has_conflicts());
+        assert_eq!(warp_index.entries.len(), 3);
+    }
+
+    #[test]
+    fn test_cache_tree_counts_and_invalidation() {
+        let mut warp_index = WarpIndex::parse(&mut Cursor::new(INDEX_DATA), ObjectFormat::Sha1).unwrap();
+        let mut cache_tree = warp_index.extensions.clone().unwrap().extension_data;
+        cache_tree.entry_count = 1234;
+        cache_tree.subtrees.as_mut().unwrap()[0].entry_count = 10;
+
+        let bytes = cache_tree.to_bytes(&mut Vec::new());
+        assert!(bytes.starts_with(b"\x001234 2\n"));
+        let parsed = CacheTreeEntry::parse(&bytes, ObjectFormat::Sha1).unwrap();
+        assert_eq!(parsed.entry_count, 1234);
+        assert_eq!(parsed.subtrees.as_ref().unwrap()[0].entry_count, 10);
+
+        // Only the directories leading to the path lose their trees.
+        warp_index.invalidate_path("src/db/postgres.txt");
+        let cache_tree = &warp_index.extensions.as_ref().unwrap().extension_data;
+        let subtrees = cache_tree.subtrees.as_ref().unwrap();
+        assert_eq!((cache_tree.entry_count, subtrees[0].entry_count, subtrees[1].entry_count), (-1, -1, 1));
+        assert_eq!(subtrees[0].subtrees.as_ref().unwrap()[0].entry_count, -1);
+        let zed = subtrees[1].sha;
+
+        warp_index.update_checksum();
+        let bytes = warp_index.to_bytes();
+        let reparsed = WarpIndex::parse(&mut Cursor::new(bytes.as_slice()), ObjectFormat::Sha1).unwrap();
+        let cache_tree = &reparsed.extensions.as_ref().unwrap().extension_data;
+        assert!(!cache_tree.is_valid());
+        assert_eq!(cache_tree.subtrees.as_ref().unwrap()[1].sha, zed);
+        assert_eq!(reparsed.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_corrupt_index_errors() {
+        let parse = |bytes: &[u8]| WarpIndex::parse(&mut Cursor::new(bytes), ObjectFormat::Sha1).err();
+        let with_bytes = |offset: usize, replacement: &[u8]| {
+            let mut bytes = INDEX_DATA.to_vec();
+            bytes[offset..offset + replacement.len()].copy_from_slice(replacement);
+            bytes
+        };
+
+        assert_eq!(parse(&with_bytes(0, b"PACK")), Some(IndexParseError::InvalidSignature { offset: 0, found: *b"PACK" }));
+        assert_eq!(parse(&with_bytes(4, &[0, 0, 0, 9])), Some(IndexParseError::UnsupportedVersion { offset: 4, version: 9 }));
+        assert_eq!(parse(&INDEX_DATA[..8]), Some(IndexParseError::Truncated { offset: 8 }));
+        // The second entry starts at offset 84.
+        assert_eq!(parse(&INDEX_DATA[..100]), Some(IndexParseError::TruncatedEntry { offset: 84 }));
+        assert!(matches!(parse(&with_bytes(0x194, b"tree")), Some(IndexParseError::InvalidExtension { offset: 0x194, .. })));
+        assert!(matches!(parse(&with_bytes(0x198, &[0, 0, 1, 0])), Some(IndexParseError::InvalidExtension { offset: 0x194, .. })));
+
+        let flipped = with_bytes(0x60, b"X");
+        match parse(&flipped) {
+            Some(IndexParseError::ChecksumMismatch { offset, expected, .. }) => {
+                assert_eq!(offset as usize, INDEX_DATA.len() - 20);
+                assert_eq!(expected.as_bytes(), &INDEX_DATA[INDEX_DATA.len() - 20..]);
+            },
+            other => panic!("expected a checksum mismatch, got {:?}", other),
+        }
+        assert!(parse(&flipped).unwrap().to_string().contains("checksum mismatch at offset"));
+
+        // Optional extensions we do not understand are skipped.
+        let mut warp_index = WarpIndex::parse(&mut Cursor::new(NO_TREE), ObjectFormat::Sha1).unwrap();
+        warp_index.update_checksum();
+        let mut bytes = warp_index.to_bytes();
+        bytes.truncate(bytes.len() - 20);
+        bytes.extend(b"REUC\0\0\0\x02ab");
+        bytes.extend(ObjectFormat::Sha1.digest(&bytes).as_bytes());
+        assert_eq!(WarpIndex::parse(&mut Cursor::new(bytes.as_slice()), ObjectFormat::Sha1).unwrap().entries.len(), 3);
+    }
 }
@@ -0,0 +1,295 @@
+use std::{fs, io::{self, Read, Write}, path::{Path, PathBuf}, sync::atomic::{AtomicUsize, Ordering}};
+
+use sha2::{Digest, Sha256};
+
+use crate::auxiliary::{glob_match, warp_dir};
+use crate::config::Config;
+use crate::object_store::{ObjectKind, ObjectStore};
+use crate::reachable::{repository_roots, walk};
+use crate::refs::Refs;
+
+pub const POINTER_VERSION: &str = "https://git-lfs.github.com/spec/v1";
+
+// Pointers are a few lines of text; larger blobs are never pointers.
+const MAX_POINTER_SIZE: usize = 1024;
+
+const COPY_CHUNK: usize = 64 * 1024;
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// The small text blob committed in place of a large file, in the git-lfs
+// pointer format: the spec version, the SHA-256 of the content and its
+// size, one `key value` per line.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Pointer {
+    pub oid: String,
+    pub size: u64
+}
+
+impl Pointer {
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() > MAX_POINTER_SIZE {
+            return None;
+        }
+        let text = std::str::from_utf8(bytes).ok()?;
+        let mut lines = text.strip_suffix('\n')?.split('\n');
+
+        if lines.next()? != format!("version {}", POINTER_VERSION) {
+            return None;
+        }
+        let oid = lines.next()?.strip_prefix("oid sha256:")?;
+        if oid.len() != 64 || !oid.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) {
+            return None;
+        }
+        let size = lines.next()?.strip_prefix("size ")?.parse().ok()?;
+        if lines.next().is_some() {
+            return None;
+        }
+
+        Some(Self { oid: oid.to_string(), size })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        format!("version {}\noid sha256:{}\nsize {}\n", POINTER_VERSION, self.oid, self.size).into_bytes()
+    }
+}
+
+// Copy `reader` to `writer` and describe what went through.
+fn copy_hashed<R: Read, W: Write>(mut reader: R, mut writer: W) -> io::Result<Pointer> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; COPY_CHUNK];
+    let mut size = 0u64;
+    loop {
+        let read = reader.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+        writer.write_all(&buf[..read])?;
+        size += read as u64;
+    }
+    writer.flush()?;
+    Ok(Pointer { oid: hex::encode(hasher.finalize()), size })
+}
+
+// Which files are stored as pointers: those of at least `lfs.threshold`
+// bytes, and those matching one of the whitespace-separated patterns in
+// `lfs.track`. Patterns without a `/` match the file name in any directory.
+#[derive(Clone, Debug, Default)]
+pub struct LfsRules {
+    threshold: Option<u64>,
+    patterns: Vec<String>
+}
+
+impl LfsRules {
+    pub fn from_config(config: &Config) -> io::Result<Self> {
+        let threshold = config.get_size("lfs.threshold")?;
+        let patterns = config.get("lfs.track").unwrap_or_default().split_whitespace().map(str::to_string).collect();
+        Ok(Self { threshold, patterns })
+    }
+
+    pub fn tracks(&self, path: &Path, size: u64) -> bool {
+        if self.threshold.is_some_and(|threshold| size >= threshold) {
+            return true;
+        }
+
+        let path = path.to_string_lossy();
+        let path = path.trim_start_matches("./");
+        let name = path.rsplit('/').next().unwrap_or(path);
+        self.patterns.iter().any(|pattern| match pattern.contains('/') {
+            true => glob_match(pattern.trim_start_matches('/').as_bytes(), path.as_bytes()),
+            false => glob_match(pattern.as_bytes(), name.as_bytes()),
+        })
+    }
+}
+
+// Large file contents, stored whole under `.warp/lfs/objects/<sha256>`.
+#[derive(Clone, Debug)]
+pub struct LfsStore {
+    objects_dir: PathBuf
+}
+
+impl LfsStore {
+    pub fn new(objects_dir: PathBuf) -> Self {
+        Self {
+            objects_dir
+        }
+    }
+
+    pub fn open() -> Self {
+        Self::new(warp_dir().join("lfs").join("objects"))
+    }
+
+    // The store of another repository, given its working directory or its
+    // `.warp` directory.
+    pub fn at_repository(path: &Path) -> io::Result<Self> {
+        for warp_dir in [path.join(".warp"), path.to_path_buf()] {
+            if warp_dir.join("objects").is_dir() {
+                return Ok(Self::new(warp_dir.join("lfs").join("objects")));
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::NotFound, format!("{} is not a warp repository", path.display())))
+    }
+
+    pub fn path(&self, pointer: &Pointer) -> PathBuf {
+        self.objects_dir.join(&pointer.oid)
+    }
+
+    // Whether the store holds the object with the right content. The size is
+    // compared first so that most mismatches are caught without hashing.
+    pub fn contains(&self, pointer: &Pointer) -> bool {
+        if !fs::metadata(self.path(pointer)).is_ok_and(|metadata| metadata.len() == pointer.size) {
+            return false;
+        }
+        self.open_content(pointer).and_then(|file| copy_hashed(file, io::sink())).is_ok_and(|copied| copied == *pointer)
+    }
+
+    pub fn open_content(&self, pointer: &Pointer) -> io::Result<fs::File> {
+        fs::File::open(self.path(pointer))
+    }
+
+    // Stream content into the store, returning its pointer. The content is
+    // written to a temporary file first and renamed once its id is known.
+    pub fn insert<R: Read>(&self, reader: R) -> io::Result<Pointer> {
+        self.insert_expected(reader, None)
+    }
+
+    // Like `insert`, but when `expected` is given the content must hash to
+    // it; otherwise only the temporary file is removed and the store is left
+    // as it was.
+    fn insert_expected<R: Read>(&self, reader: R, expected: Option<&Pointer>) -> io::Result<Pointer> {
+        fs::create_dir_all(&self.objects_dir)?;
+        let tmp_path = self.objects_dir.join(format!("tmp_{}_{}", std::process::id(), TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let result = (|| {
+            let file = fs::File::create(&tmp_path)?;
+            let pointer = copy_hashed(reader, io::BufWriter::new(&file))?;
+            file.sync_all()?;
+            if expected.is_some_and(|expected| *expected != pointer) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "content does not match its pointer"));
+            }
+            if self.contains(&pointer) {
+                fs::remove_file(&tmp_path)?;
+            } else {
+                // Replaces a corrupt copy, if there was one.
+                fs::rename(&tmp_path, self.path(&pointer))?;
+            }
+            Ok(pointer)
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result
+    }
+
+    // Write one object's content to `target` once it hashes to the pointer's
+    // id. The content goes to a temporary file beside `target` first, so a
+    // corrupt object leaves nothing behind.
+    pub fn materialize(&self, pointer: &Pointer, target: &Path) -> io::Result<()> {
+        let dir = target.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let tmp_path = dir.join(format!(".warp_lfs_tmp_{}_{}", std::process::id(), TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let result = (|| {
+            let copied = copy_hashed(self.open_content(pointer)?, io::BufWriter::new(fs::File::create(&tmp_path)?))?;
+            if copied != *pointer {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} in {} is corrupt", pointer.oid, self.objects_dir.display())));
+            }
+            fs::rename(&tmp_path, target)
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result
+    }
+
+    // Copy one object from `source`, checking that its content matches.
+    pub fn copy_from(&self, source: &LfsStore, pointer: &Pointer) -> io::Result<()> {
+        match self.insert_expected(source.open_content(pointer)?, Some(pointer)) {
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => Err(io::Error::new(
+                io::ErrorKind::InvalidData, format!("{} in {} is corrupt", pointer.oid, source.objects_dir.display())
+            )),
+            result => result.map(|_| ()),
+        }
+    }
+}
+
+// The pointer to commit for `path` if the repository's rules track it. The
+// content is moved into the LFS store unless only hashing (`write` unset).
+pub fn clean_file(path: &Path, write: bool) -> io::Result<Option<Pointer>> {
+    let size = fs::metadata(path)?.len();
+    if !LfsRules::from_config(&Config::open()?)?.tracks(path, size) {
+        return Ok(None);
+    }
+
+    let file = fs::File::open(path)?;
+    let pointer = match write {
+        true => LfsStore::open().insert(file)?,
+        false => copy_hashed(file, io::sink())?,
+    };
+    Ok(Some(pointer))
+}
+
+// Every pointer in a blob reachable from the refs, HEAD, reflogs or index.
+pub fn reachable_pointers(store: &ObjectStore, refs: &Refs) -> io::Result<Vec<Pointer>> {
+    let mut pointers = Vec::new();
+    for object in walk(store, &repository_roots(refs)?)? {
+        if object.kind != ObjectKind::Blob {
+            continue;
+        }
+        if store.read_header(&object.oid)?.1 > MAX_POINTER_SIZE {
+            continue;
+        }
+        let (_, bytes) = store.read(&object.oid)?;
+        if let Some(pointer) = Pointer::parse(&bytes) {
+            if !pointers.contains(&pointer) {
+                pointers.push(pointer);
+            }
+        }
+    }
+    Ok(pointers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pointer_and_rules() {
+        let dir = std::env::temp_dir().join(format!("warp-lfs-{}", std::process::id()));
+        let store = LfsStore::new(dir.clone());
+        let pointer = store.insert(&b"hello\n"[..]).unwrap();
+        // `printf 'hello\n' | sha256sum`
+        assert_eq!(pointer.oid, "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03");
+        assert!(store.contains(&pointer));
+        assert_eq!(fs::read(store.path(&pointer)).unwrap(), b"hello\n");
+
+        // A corrupt copy never replaces a good one, and is never checked out.
+        let target = dir.join("hello.txt");
+        store.materialize(&pointer, &target).unwrap();
+        assert_eq!(fs::read(&target).unwrap(), b"hello\n");
+        let corrupt = LfsStore::new(dir.join("corrupt"));
+        fs::create_dir_all(dir.join("corrupt")).unwrap();
+        fs::write(corrupt.path(&pointer), b"HELLO\n").unwrap();
+        assert!(!corrupt.contains(&pointer));
+        assert!(store.copy_from(&corrupt, &pointer).is_err());
+        assert_eq!(fs::read(store.path(&pointer)).unwrap(), b"hello\n");
+        assert!(corrupt.materialize(&pointer, &dir.join("corrupt.txt")).is_err());
+        assert!(!dir.join("corrupt.txt").exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+
+        // A good copy replaces a corrupt one.
+        corrupt.copy_from(&store, &pointer).unwrap();
+        assert!(corrupt.contains(&pointer));
+
+        let text = pointer.to_bytes();
+        assert_eq!(String::from_utf8_lossy(&text), format!("version {}\noid sha256:{}\nsize 6\n", POINTER_VERSION, pointer.oid));
+        assert_eq!(Pointer::parse(&text), Some(pointer));
+        assert_eq!(Pointer::parse(b"hello\n"), None);
+
+        let rules = LfsRules { threshold: Some(1024), patterns: vec!["*.psd".to_string(), "assets/*.bin".to_string()] };
+        assert!(rules.tracks(Path::new("art/cover.psd"), 10));
+        assert!(rules.tracks(Path::new("assets/model.bin"), 10));
+        assert!(!rules.tracks(Path::new("src/model.bin"), 10));
+        assert!(rules.tracks(Path::new("src/model.bin"), 1024));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,10 +1,29 @@
-mod args;
+#![allow(non_snake_case)]
+pub mod args;
 pub mod commands;
-mod auxiliary;
-mod blob;
-mod tree;
-mod index;
-mod commit;
+pub mod auxiliary;
+pub mod blob;
+pub mod bundle;
+pub mod chunking;
+pub mod tree;
+pub mod index;
+pub mod lfs;
+pub mod commit;
+pub mod compression;
+pub mod config;
+pub mod delta;
+pub mod fast_export;
+pub mod fast_import;
+pub mod fsck;
+pub mod git_import;
+pub mod object;
+pub mod object_id;
+pub mod object_store;
+pub mod pack;
+pub mod pack_reader;
+pub mod reachable;
+pub mod refs;
+pub mod tag;
 use std::path::PathBuf;
 use std::sync::OnceLock;
 
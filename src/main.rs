@@ -1,56 +1,80 @@
-mod args;
-mod commands;
-mod auxiliary;
-mod blob;
-mod index;
-mod commit;
-
+#![allow(non_snake_case)]
 use std::fs;
-use std::io::{Cursor, Read, Write};
+use std::io::Cursor;
 
-use blob::Blob;
 use clap::Parser;
-use commands::{init, add};
-use index::{WarpIndex, INDEX_DATA, NO_TREE};
-use crate::args::Commands::{Init, Hash, Add, UpdateIndex, WriteTree, TestTree, CommitTree};
-use crate::args::Warp;
-use crate::commit::Commit;
+use ChronoSync::blob::Blob;
+use ChronoSync::commands::{init, add, bundle_create, bundle_unbundle, bundle_verify, cat_file, checkout_index, fast_export, fast_import, fsck, gc, import_git, lfs_fetch, lfs_push, ls_files, prune, tag};
+use ChronoSync::index::WarpIndex;
+use ChronoSync::args::Commands::{Init, Hash, Add, UpdateIndex, WriteTree, LsFiles, TestTree, CommitTree, Gc, Tag, Prune, ImportGit, FastExport, FastImport, Bundle, CheckoutIndex, Lfs, Fsck, CatFile};
+use ChronoSync::args::{BundleCommand, LfsCommand};
+use ChronoSync::args::Warp;
+use ChronoSync::commit::Commit;
+use ChronoSync::object_store::ObjectStore;
+use ChronoSync::pack::PackOptions;
 
 
 
-#[warn(unused_variables)]
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Warp::parse();
     match args.command {
-        Init => init(),
+        Init { object_format, compression } => init(object_format, compression),
         Hash { path } => {
-                        // let hash_result = hash_object(path)?;
                         let new_blob = Blob::new(path);
-                        Blob::compress_to_object(&new_blob);
+                        println!("{}", new_blob.compress_to_object()?);
                         Ok(())
             }
         Add { path } => add(path),
-        UpdateIndex { add } => {
-            WarpIndex::update_index(add);
-            
+        UpdateIndex { add, index_info, index_version } => {
+            if !add.is_empty() {
+                WarpIndex::update_index(add)?;
+            }
+            if index_info {
+                WarpIndex::update_index_info(std::io::stdin().lock())?;
+            }
+            if let Some(version) = index_version {
+                WarpIndex::convert_version(version)?;
+            }
 
             Ok(())
         },
         WriteTree => {
             // Creating an extension from an entry.c
-            WarpIndex::write_tree();
+            WarpIndex::write_tree()?;
             Ok(())
         },
+        LsFiles { stage, unmerged } => ls_files(stage, unmerged),
         CommitTree { tree, parents, message } => {
-            let new_commit = Commit::new(tree, parents, message);
-            Commit::compress_to_object(&new_commit);
+            let store = ObjectStore::open()?;
+            let parents = parents.iter().map(|parent| store.resolve(parent)).collect::<Result<Vec<_>, _>>()?;
+            let new_commit = Commit::new(store.resolve(&tree)?, parents, message);
+            println!("{}", new_commit.compress_to_object()?);
             Ok(())
         },
         TestTree { path } => {
-            let contents = fs::read(path).unwrap();
+            let format = ObjectStore::open()?.format();
+            let contents = fs::read(path)?;
             let mut cursor = Cursor::new(contents.as_slice());
-            println!("{:?}", WarpIndex::try_from(&mut cursor).unwrap());
+            println!("{:?}", WarpIndex::parse(&mut cursor, format)?);
             Ok(())
-        }
+        },
+        Gc { window, depth } => gc(PackOptions { window, depth }),
+        Tag { annotate, message, delete, list, force, points_at, names } => tag(annotate, message, delete, list, force, points_at, names),
+        Prune { expire, dry_run } => prune(expire, dry_run),
+        ImportGit { path } => import_git(path),
+        FastExport { refs } => fast_export(refs),
+        FastImport => fast_import(),
+        Bundle { command } => match command {
+            BundleCommand::Create { file, all, revisions } => bundle_create(file, revisions, all),
+            BundleCommand::Verify { file } => bundle_verify(file),
+            BundleCommand::Unbundle { file } => bundle_unbundle(file),
+        },
+        CheckoutIndex { all, force, paths } => checkout_index(all, force, paths),
+        Lfs { command } => match command {
+            LfsCommand::Fetch { remote } => lfs_fetch(remote),
+            LfsCommand::Push { remote } => lfs_push(remote),
+        },
+        Fsck => fsck(),
+        CatFile { kind, size, pretty, exists, object } => cat_file(kind, size, pretty, exists, object),
     }
 }
@@ -0,0 +1,233 @@
+use std::io;
+
+use crate::commit::Commit;
+use crate::object_id::{ObjectFormat, ObjectId};
+use crate::object_store::{ObjectKind, ObjectStore};
+use crate::tag::Tag;
+use crate::tree::Tree;
+
+// A parsed object read back from the object store.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Object {
+    Blob(Vec<u8>),
+    Tree(Tree),
+    Commit(Commit),
+    Tag(Tag)
+}
+
+impl Object {
+    pub fn parse(kind: ObjectKind, bytes: &[u8], format: ObjectFormat) -> io::Result<Self> {
+        Ok(match kind {
+            ObjectKind::Blob => Object::Blob(bytes.to_vec()),
+            ObjectKind::Tree => Object::Tree(Tree::parse(bytes, format)?),
+            ObjectKind::Commit => Object::Commit(Commit::parse(bytes)?),
+            ObjectKind::Tag => Object::Tag(Tag::parse(bytes)?),
+        })
+    }
+
+    pub fn read(store: &ObjectStore, oid: &ObjectId) -> io::Result<Self> {
+        let (kind, bytes) = store.read(oid)?;
+        Object::parse(kind, &bytes, store.format())
+    }
+
+    pub fn kind(&self) -> ObjectKind {
+        match self {
+            Object::Blob(_) => ObjectKind::Blob,
+            Object::Tree(_) => ObjectKind::Tree,
+            Object::Commit(_) => ObjectKind::Commit,
+            Object::Tag(_) => ObjectKind::Tag,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Object::Blob(content) => content.clone(),
+            Object::Tree(tree) => tree.to_bytes(),
+            Object::Commit(commit) => commit.to_bytes(),
+            Object::Tag(tag) => tag.to_bytes(),
+        }
+    }
+}
+
+pub(crate) fn invalid_object(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+// `key value` header lines of a commit or tag, in order.
+pub(crate) type Headers = Vec<(String, Vec<u8>)>;
+
+// Split a commit or tag body into its `key value` header lines and the
+// message that follows the first blank line. Continuation lines (those
+// starting with a space) are folded into the previous value joined by `\n`.
+// Values and the message are kept as raw bytes: an `encoding` header lets
+// them be in something other than UTF-8. The message is `None` when the
+// headers run to the end without a blank line.
+pub(crate) fn parse_headers(bytes: &[u8]) -> io::Result<(Headers, Option<Vec<u8>>)> {
+    let mut headers = Headers::new();
+    let mut rest = bytes;
+
+    loop {
+        let (line, remaining) = match rest.iter().position(|&b| b == b'\n') {
+            Some(newline) => (&rest[..newline], &rest[newline + 1..]),
+            None if rest.is_empty() => return Ok((headers, None)),
+            None => return Err(invalid_object("object headers are not newline terminated")),
+        };
+        rest = remaining;
+
+        if line.is_empty() {
+            return Ok((headers, Some(rest.to_vec())));
+        }
+
+        if let Some(continuation) = line.strip_prefix(b" ") {
+            let (_, value) = headers.last_mut().ok_or_else(|| invalid_object("continuation line without a header"))?;
+            value.push(b'\n');
+            value.extend(continuation);
+        } else {
+            let space = line.iter().position(|&b| b == b' ').ok_or_else(|| invalid_object("malformed header line"))?;
+            let key = std::str::from_utf8(&line[..space]).map_err(|_| invalid_object("header name is not valid UTF-8"))?;
+            headers.push((key.to_string(), line[space + 1..].to_vec()));
+        }
+    }
+}
+
+// A header value that must be text, such as an object id or a type.
+pub(crate) fn header_text<'a>(key: &str, value: &'a [u8]) -> io::Result<&'a str> {
+    std::str::from_utf8(value).map_err(|_| invalid_object(&format!("{} header is not valid UTF-8", key)))
+}
+
+// Inverse of `parse_headers`. The blank line before the message is left out
+// only without `separator` and with an empty message.
+pub(crate) fn write_headers(headers: &[(String, Vec<u8>)], message: &[u8], separator: bool) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for (key, value) in headers {
+        buf.extend(key.as_bytes());
+        buf.push(b' ');
+        for &byte in value {
+            buf.push(byte);
+            if byte == b'\n' {
+                buf.push(b' ');
+            }
+        }
+        buf.push(b'\n');
+    }
+    if separator || !message.is_empty() {
+        buf.push(b'\n');
+    }
+    buf.extend(message);
+
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMIT: &[u8] = b"tree fd6be7f413d69b5f59c60e85929688da5fa22705
+parent ce013625030ba8dba906f756967f9e9ca394464a
+parent add4794d9c94872b96c1697c056fb82ae0d72880
+author A U Thor <author@example.com> 1700000000 +0530
+committer C O Mitter <committer@example.com> 1700000100 -0000
+encoding ISO-8859-1
+gpgsig -----BEGIN PGP SIGNATURE-----\n \n iQEzBAABCAAdFiEE\n -----END PGP SIGNATURE-----
+
+Merge things
+
+With a body.
+";
+
+    const TAG: &[u8] = b"object fd6be7f413d69b5f59c60e85929688da5fa22705
+type commit
+tag v1.0
+tagger T Agger <tagger@example.com> 1700000000 +0000
+
+Release 1.0
+";
+
+    #[test]
+    fn test_commit_round_trip() {
+        let object = Object::parse(ObjectKind::Commit, COMMIT, ObjectFormat::Sha1).unwrap();
+        let Object::Commit(commit) = &object else { panic!("expected a commit") };
+
+        assert_eq!(commit.parents.len(), 2);
+        assert_eq!(commit.author.name, b"A U Thor");
+        assert_eq!(commit.author.offset_minutes(), 330);
+        assert_eq!(commit.committer.timezone, "-0000");
+        assert_eq!(commit.extra_headers[0], ("encoding".to_string(), b"ISO-8859-1".to_vec()));
+        assert_eq!(commit.message, b"Merge things\n\nWith a body.\n");
+        assert_eq!(object.to_bytes(), COMMIT);
+    }
+
+    #[test]
+    fn test_latin1_commit_round_trip() {
+        let bytes = b"tree fd6be7f413d69b5f59c60e85929688da5fa22705
+author Ren\xe9 <rene@example.com> 1700000000 +0100
+committer Ren\xe9 <rene@example.com> 1700000000 +0100
+encoding ISO-8859-1
+
+Caf\xe9
+";
+        let object = Object::parse(ObjectKind::Commit, bytes, ObjectFormat::Sha1).unwrap();
+        let Object::Commit(commit) = &object else { panic!("expected a commit") };
+
+        assert_eq!(commit.author.name, b"Ren\xe9");
+        assert_eq!(commit.message, b"Caf\xe9\n");
+        assert_eq!(object.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_tag_round_trip() {
+        let object = Object::parse(ObjectKind::Tag, TAG, ObjectFormat::Sha1).unwrap();
+        let Object::Tag(tag) = &object else { panic!("expected a tag") };
+
+        assert_eq!(tag.kind, ObjectKind::Commit);
+        assert_eq!(tag.name, "v1.0");
+        assert_eq!(object.to_bytes(), TAG);
+    }
+
+    #[test]
+    fn test_unusual_spacing_round_trips() {
+        // Doubled spaces, a zero-padded timestamp and no blank line after the
+        // headers of a commit without a message.
+        let bytes = b"tree fd6be7f413d69b5f59c60e85929688da5fa22705
+author A U Thor  <author@example.com>  0001700000000 +0000
+committer C O Mitter <committer@example.com> 1700000100  -0100
+";
+        let object = Object::parse(ObjectKind::Commit, bytes, ObjectFormat::Sha1).unwrap();
+        let Object::Commit(commit) = &object else { panic!("expected a commit") };
+
+        assert_eq!(commit.author.name, b"A U Thor");
+        assert_eq!(commit.author.timestamp, 1700000000);
+        assert_eq!(commit.committer.offset_minutes(), -60);
+        assert!(commit.message.is_empty() && !commit.separator);
+        assert_eq!(object.to_bytes(), bytes);
+
+        let mut edited = commit.clone();
+        edited.author.timestamp += 1;
+        edited.message = b"Now with a message\n".to_vec();
+        assert!(edited.to_bytes().ends_with(b"<author@example.com> 1700000001 +0000\ncommitter C O Mitter <committer@example.com> 1700000100  -0100\n\nNow with a message\n"));
+
+        let tag = b"object fd6be7f413d69b5f59c60e85929688da5fa22705\ntype commit\ntag v1.0\n";
+        assert_eq!(Object::parse(ObjectKind::Tag, tag, ObjectFormat::Sha1).unwrap().to_bytes(), tag);
+    }
+
+    #[test]
+    fn test_tree_round_trip() {
+        let mut bytes = b"100644 a.txt\0".to_vec();
+        bytes.extend([0x11; 20]);
+        bytes.extend(b"40000 src\0");
+        bytes.extend([0x22; 20]);
+        bytes.extend(b"040000 legacy\0");
+        bytes.extend([0x33; 20]);
+        bytes.extend(b"100644 caf\xe9.txt\0");
+        bytes.extend([0x44; 20]);
+
+        let object = Object::parse(ObjectKind::Tree, &bytes, ObjectFormat::Sha1).unwrap();
+        let Object::Tree(tree) = &object else { panic!("expected a tree") };
+
+        assert_eq!(tree.entries[1].kind(), ObjectKind::Tree);
+        assert_eq!(tree.entries[2].kind(), ObjectKind::Tree);
+        assert_eq!(tree.entries[2].mode_bytes(), b"040000");
+        assert_eq!(tree.entries[3].name, b"caf\xe9.txt");
+        assert_eq!(object.to_bytes(), bytes);
+    }
+}
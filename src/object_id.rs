@@ -0,0 +1,177 @@
+use core::fmt;
+use std::{io, str::FromStr};
+
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+use crate::object_store::ObjectKind;
+
+// The hash function a repository names its objects with. Recorded as
+// `extensions.objectformat` in `.warp/config`; SHA-1 when absent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ObjectFormat {
+    #[default]
+    Sha1,
+    Sha256
+}
+
+impl ObjectFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectFormat::Sha1 => "sha1",
+            ObjectFormat::Sha256 => "sha256",
+        }
+    }
+
+    // Width of a binary object id.
+    pub fn raw_len(&self) -> usize {
+        match self {
+            ObjectFormat::Sha1 => 20,
+            ObjectFormat::Sha256 => 32,
+        }
+    }
+
+    pub fn hex_len(&self) -> usize {
+        self.raw_len() * 2
+    }
+
+    pub fn hasher(&self) -> ObjectHasher {
+        match self {
+            ObjectFormat::Sha1 => ObjectHasher::Sha1(Sha1::new()),
+            ObjectFormat::Sha256 => ObjectHasher::Sha256(Sha256::new()),
+        }
+    }
+
+    // Hash arbitrary bytes, as used for index and pack checksums.
+    pub fn digest(&self, bytes: &[u8]) -> ObjectId {
+        let mut hasher = self.hasher();
+        hasher.update(bytes);
+        hasher.finalize()
+    }
+
+    // The id of an object: the hash of its `<type> <size>\0` header and body.
+    pub fn hash_object(&self, kind: ObjectKind, bytes: &[u8]) -> ObjectId {
+        let mut hasher = self.hasher();
+        hasher.update(&kind.header(bytes.len()));
+        hasher.update(bytes);
+        hasher.finalize()
+    }
+
+    fn from_raw_len(len: usize) -> Option<Self> {
+        match len {
+            20 => Some(ObjectFormat::Sha1),
+            32 => Some(ObjectFormat::Sha256),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ObjectFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ObjectFormat {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "sha1" => Ok(ObjectFormat::Sha1),
+            "sha256" => Ok(ObjectFormat::Sha256),
+            other => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown object format '{}'", other))),
+        }
+    }
+}
+
+pub enum ObjectHasher {
+    Sha1(Sha1),
+    Sha256(Sha256)
+}
+
+impl ObjectHasher {
+    pub fn update(&mut self, bytes: &[u8]) {
+        match self {
+            ObjectHasher::Sha1(hasher) => hasher.update(bytes),
+            ObjectHasher::Sha256(hasher) => hasher.update(bytes),
+        }
+    }
+
+    pub fn finalize(self) -> ObjectId {
+        match self {
+            ObjectHasher::Sha1(hasher) => ObjectId::from_slice(&hasher.finalize()),
+            ObjectHasher::Sha256(hasher) => ObjectId::from_slice(&hasher.finalize()),
+        }
+        .expect("digest has the width of its format")
+    }
+}
+
+// The name of an object, 20 bytes for SHA-1 repositories and 32 bytes for
+// SHA-256 ones. Ids of the same format order by their bytes.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ObjectId {
+    bytes: [u8; 32],
+    len: u8
+}
+
+impl ObjectId {
+    pub fn from_slice(bytes: &[u8]) -> Option<Self> {
+        ObjectFormat::from_raw_len(bytes.len())?;
+        let mut id = [0u8; 32];
+        id[..bytes.len()].copy_from_slice(bytes);
+        Some(ObjectId { bytes: id, len: bytes.len() as u8 })
+    }
+
+    // The all-zero id, used where git writes a null object id.
+    pub fn null(format: ObjectFormat) -> Self {
+        ObjectId { bytes: [0u8; 32], len: format.raw_len() as u8 }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+
+    pub fn format(&self) -> ObjectFormat {
+        ObjectFormat::from_raw_len(self.len as usize).expect("object id has a known width")
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.as_bytes())
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl fmt::Debug for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ObjectId({})", self.to_hex())
+    }
+}
+
+impl FromStr for ObjectId {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("'{}' is not a valid object id", s));
+        let bytes = hex::decode(s).map_err(|_| invalid())?;
+        ObjectId::from_slice(&bytes).ok_or_else(invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha256_object_id() {
+        // `printf 'hello\n' | git hash-object --object-format=sha256 --stdin`
+        let oid = ObjectFormat::Sha256.hash_object(ObjectKind::Blob, b"hello\n");
+        assert_eq!(oid.to_hex(), "2cf8d83d9ee29543b34a87727421fdecb7e3f3a183d337639025de576db9ebb4");
+        assert_eq!(oid.format(), ObjectFormat::Sha256);
+        assert_eq!(oid.to_hex().parse::<ObjectId>().unwrap(), oid);
+    }
+}
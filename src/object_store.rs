@@ -0,0 +1,640 @@
+use core::fmt;
+use std::{collections::HashSet, fs, io::{self, BufReader, BufWriter, Read, Write}, path::{Path, PathBuf}, str::FromStr, sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex}, time::SystemTime};
+
+use crate::auxiliary::warp_dir;
+use crate::compression::{decoder, decompress, Codec};
+use crate::config::Config;
+use crate::object_id::{ObjectFormat, ObjectId};
+use crate::pack_reader::Packs;
+
+// Files are streamed through the hasher and compressor in chunks this big.
+const STREAM_CHUNK: usize = 64 * 1024;
+
+// Shortest abbreviated object id that will be expanded.
+const MIN_ABBREV: usize = 4;
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// The four kinds of object that can live in the object database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ObjectKind {
+    Blob,
+    Tree,
+    Commit,
+    Tag
+}
+
+impl ObjectKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectKind::Blob => "blob",
+            ObjectKind::Tree => "tree",
+            ObjectKind::Commit => "commit",
+            ObjectKind::Tag => "tag",
+        }
+    }
+
+    // Build the `<type> <size>\0` header that prefixes every stored object.
+    pub fn header(&self, size: usize) -> Vec<u8> {
+        let mut header = format!("{} {}", self.as_str(), size).into_bytes();
+        header.push(0);
+        header
+    }
+}
+
+impl fmt::Display for ObjectKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ObjectKind {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blob" => Ok(ObjectKind::Blob),
+            "tree" => Ok(ObjectKind::Tree),
+            "commit" => Ok(ObjectKind::Commit),
+            "tag" => Ok(ObjectKind::Tag),
+            other => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown object type '{}'", other))),
+        }
+    }
+}
+
+// Object database rooted at `.warp/objects`. Loose objects are stored
+// compressed with the repository's codec under `<first two hex digits>/<remaining hex digits>`; anything
+// not found there is looked up in the packs under `objects/pack`, and then
+// in the object directories listed in `objects/info/alternates`.
+#[derive(Clone, Debug)]
+pub struct ObjectStore {
+    objects_dir: PathBuf,
+    format: ObjectFormat,
+    codec: Codec,
+    packs: Arc<Mutex<Packs>>,
+    // Every alternate reachable through `info/alternates`, flattened in
+    // lookup order. Alternates are only ever read from.
+    alternates: Vec<ObjectStore>
+}
+
+impl ObjectStore {
+    pub fn new(objects_dir: PathBuf, format: ObjectFormat) -> Self {
+        Self {
+            objects_dir,
+            format,
+            codec: Codec::default(),
+            packs: Arc::new(Mutex::new(Packs::default())),
+            alternates: Vec::new()
+        }
+    }
+
+    // Open the object store of the repository in the current working
+    // directory, using the object format and codec recorded in its config.
+    pub fn open() -> io::Result<Self> {
+        let config = Config::open()?;
+        Self::new(warp_dir().join("objects"), config.object_format()?)
+            .with_codec(config.compression()?)
+            .with_alternates()
+    }
+
+    // Compress newly written loose objects with `codec`. Existing objects are
+    // readable whatever codec wrote them.
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    // Load `info/alternates`, following the alternates of alternates too.
+    // Directories already visited are skipped, so cycles terminate.
+    pub fn with_alternates(mut self) -> io::Result<Self> {
+        let mut visited = HashSet::new();
+        visited.insert(fs::canonicalize(&self.objects_dir).unwrap_or_else(|_| self.objects_dir.clone()));
+        let mut alternates = Vec::new();
+        collect_alternates(&self.objects_dir, self.format, &mut visited, &mut alternates)?;
+        self.alternates = alternates;
+        Ok(self)
+    }
+
+    pub fn alternates(&self) -> &[ObjectStore] {
+        &self.alternates
+    }
+
+    pub fn objects_dir(&self) -> &PathBuf {
+        &self.objects_dir
+    }
+
+    pub fn format(&self) -> ObjectFormat {
+        self.format
+    }
+
+    // Compute the object id of `bytes` without writing anything.
+    pub fn hash(&self, kind: ObjectKind, bytes: &[u8]) -> ObjectId {
+        self.format.hash_object(kind, bytes)
+    }
+
+    // Compute the object id of `size` bytes read from `reader`, in constant
+    // memory. Fails if the reader does not yield exactly `size` bytes.
+    pub fn hash_stream<R: Read>(&self, kind: ObjectKind, size: u64, mut reader: R) -> io::Result<ObjectId> {
+        let mut hasher = self.format.hasher();
+        hasher.update(&kind.header(size as usize));
+
+        let mut buf = vec![0u8; STREAM_CHUNK];
+        let mut total = 0u64;
+        loop {
+            let read = reader.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buf[..read]);
+            total += read as u64;
+        }
+        check_stream_size(size, total)?;
+
+        Ok(hasher.finalize())
+    }
+
+    fn object_path(&self, oid: &ObjectId) -> PathBuf {
+        let hex = oid.to_hex();
+        let (dir_hash, file_hash) = hex.split_at(2);
+        let mut path = self.objects_dir.clone();
+        path.push(dir_hash);
+        path.push(file_hash);
+        path
+    }
+
+    // Store an object unless it already exists. Objects are immutable, so an
+    // existing copy is as good as a new one.
+    pub fn write(&self, kind: ObjectKind, bytes: &[u8]) -> io::Result<ObjectId> {
+        let oid = self.hash(kind, bytes);
+        if self.has_object(&oid) {
+            return Ok(oid);
+        }
+        self.write_stream(kind, bytes.len() as u64, bytes)
+    }
+
+    // Hash and compress `size` bytes from `reader` in one pass, in constant
+    // memory. The compressed object goes to a temporary file in the objects
+    // directory, is fsynced and then renamed into place once its id is
+    // known, so readers and concurrent writers never see a partial object.
+    pub fn write_stream<R: Read>(&self, kind: ObjectKind, size: u64, reader: R) -> io::Result<ObjectId> {
+        self.write_loose(kind, size, reader, |oid| self.has_object(oid))
+    }
+
+    // Write a loose copy of an object that may so far only be packed, dated
+    // `modified` so that prune judges its age from when it was first stored
+    // rather than from now.
+    pub fn loosen(&self, oid: &ObjectId, modified: SystemTime) -> io::Result<()> {
+        if self.is_loose(oid) {
+            return Ok(());
+        }
+        let (kind, bytes) = self.read(oid)?;
+        self.write_loose(kind, bytes.len() as u64, bytes.as_slice(), |oid| self.is_loose(oid))?;
+        fs::File::options().write(true).open(self.object_path(oid))?.set_modified(modified)
+    }
+
+    // Stream an object into a loose file, unless `exists` says there is
+    // already a copy that will do.
+    fn write_loose<R: Read>(&self, kind: ObjectKind, size: u64, mut reader: R, exists: impl Fn(&ObjectId) -> bool) -> io::Result<ObjectId> {
+        let tmp_path = self.objects_dir.join(format!("tmp_obj_{}_{}", std::process::id(), TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let result = (|| {
+            let mut encoder = self.codec.encoder(BufWriter::new(fs::File::create(&tmp_path)?))?;
+            let mut hasher = self.format.hasher();
+            let header = kind.header(size as usize);
+            hasher.update(&header);
+            encoder.write_all(&header)?;
+
+            let mut buf = vec![0u8; STREAM_CHUNK];
+            let mut total = 0u64;
+            loop {
+                let read = reader.read(&mut buf)?;
+                if read == 0 {
+                    break;
+                }
+                hasher.update(&buf[..read]);
+                encoder.write_all(&buf[..read])?;
+                total += read as u64;
+            }
+            check_stream_size(size, total)?;
+            let file = encoder.finish()?.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()?;
+
+            let oid = hasher.finalize();
+            if exists(&oid) {
+                fs::remove_file(&tmp_path)?;
+                return Ok(oid);
+            }
+
+            let path = self.object_path(&oid);
+            let dir = path.parent().expect("object path has a fan-out directory");
+            fs::create_dir_all(dir)?;
+            fs::rename(&tmp_path, &path)?;
+            fs::File::open(dir)?.sync_all()?;
+            Ok(oid)
+        })();
+
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result
+    }
+
+    // Copy an existing pack and its index into this store, unless a pack of
+    // the same name is already there. The index is renamed into place last,
+    // since readers only look at packs that have one.
+    pub fn install_pack(&self, idx_path: &Path) -> io::Result<bool> {
+        let pack_path = idx_path.with_extension("pack");
+        let file_name = |path: &Path| path.file_name().map(|name| name.to_os_string())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a file", path.display())));
+        let target_pack = self.pack_dir().join(file_name(&pack_path)?);
+        let target_idx = self.pack_dir().join(file_name(idx_path)?);
+        if target_pack.is_file() && target_idx.is_file() {
+            return Ok(false);
+        }
+
+        fs::create_dir_all(self.pack_dir())?;
+        for (source, target) in [(&pack_path, &target_pack), (&idx_path.to_path_buf(), &target_idx)] {
+            let tmp_path = self.pack_dir().join(format!("tmp_pack_{}_{}", std::process::id(), TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+            let result = fs::copy(source, &tmp_path)
+                .and_then(|_| fs::File::open(&tmp_path)?.sync_all())
+                .and_then(|_| fs::rename(&tmp_path, target));
+            if let Err(e) = result {
+                let _ = fs::remove_file(&tmp_path);
+                return Err(e);
+            }
+        }
+        Ok(true)
+    }
+
+    // Whether the object is loose or in a pack we have already loaded. Unlike
+    // `contains` this never rescans the pack directory, which keeps writes
+    // of many new objects cheap.
+    fn has_object(&self, oid: &ObjectId) -> bool {
+        self.is_loose(oid)
+            || self.packs().contains_loaded(&self.pack_dir(), self.format, oid).unwrap_or(false)
+            || self.alternates.iter().any(|alternate| alternate.has_object(oid))
+    }
+
+    pub fn pack_dir(&self) -> PathBuf {
+        self.objects_dir.join("pack")
+    }
+
+    fn packs(&self) -> std::sync::MutexGuard<'_, Packs> {
+        self.packs.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn read(&self, oid: &ObjectId) -> io::Result<(ObjectKind, Vec<u8>)> {
+        match self.read_loose(oid) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+            result => return result,
+        }
+        if let Some(object) = self.read_packed(oid)? {
+            return Ok(object);
+        }
+        for alternate in &self.alternates {
+            match alternate.read(oid) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                result => return result,
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::NotFound, format!("object {} not found", oid)))
+    }
+
+    // Read the packed copy of an object, ignoring any loose one.
+    pub fn read_packed(&self, oid: &ObjectId) -> io::Result<Option<(ObjectKind, Vec<u8>)>> {
+        self.packs().read(&self.pack_dir(), self.format, oid)
+    }
+
+    pub fn read_loose(&self, oid: &ObjectId) -> io::Result<(ObjectKind, Vec<u8>)> {
+        let mut raw = decompress(&fs::read(self.object_path(oid))?)?;
+
+        let nul = raw.iter().position(|&b| b == 0).ok_or_else(|| corrupt_object(oid, "missing header terminator"))?;
+        let (kind, size) = parse_loose_header(oid, &raw[..nul])?;
+
+        let body = raw.split_off(nul + 1);
+        if body.len() != size {
+            return Err(corrupt_object(oid, &format!("header says {} bytes but found {}", size, body.len())));
+        }
+
+        Ok((kind, body))
+    }
+
+    // The kind and size of an object without reading all of it: loose
+    // objects are inflated only up to the end of their header, packed ones
+    // only as far as the entry headers of their delta chain.
+    pub fn read_header(&self, oid: &ObjectId) -> io::Result<(ObjectKind, usize)> {
+        match self.read_loose_header(oid) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+            result => return result,
+        }
+        if let Some(header) = self.packs().read_header(&self.pack_dir(), self.format, oid)? {
+            return Ok(header);
+        }
+        for alternate in &self.alternates {
+            match alternate.read_header(oid) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                result => return result,
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::NotFound, format!("object {} not found", oid)))
+    }
+
+    fn read_loose_header(&self, oid: &ObjectId) -> io::Result<(ObjectKind, usize)> {
+        let file = fs::File::open(self.object_path(oid))?;
+        // "commit " plus the decimal size of the largest object fits easily.
+        let mut raw = Vec::new();
+        decoder(BufReader::new(file))?.take(64).read_to_end(&mut raw)?;
+
+        let nul = raw.iter().position(|&b| b == 0).ok_or_else(|| corrupt_object(oid, "missing header terminator"))?;
+        parse_loose_header(oid, &raw[..nul])
+    }
+
+    // Expand a full or abbreviated (at least four hex digits) object id to
+    // the one object it names, loose or packed.
+    pub fn resolve(&self, name: &str) -> io::Result<ObjectId> {
+        let prefix = name.to_ascii_lowercase();
+        if prefix.len() < MIN_ABBREV || prefix.len() > self.format.hex_len() || !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("'{}' is not a valid object name", name)));
+        }
+
+        let mut candidates = Vec::new();
+        for store in std::iter::once(self).chain(&self.alternates) {
+            candidates.extend(store.loose_with_prefix(&prefix)?);
+            candidates.extend(store.packs().with_prefix(&store.pack_dir(), store.format, &prefix)?);
+        }
+        candidates.sort();
+        candidates.dedup();
+
+        match candidates.as_slice() {
+            [] => Err(io::Error::new(io::ErrorKind::NotFound, format!("object {} not found", name))),
+            [oid] => Ok(*oid),
+            _ => {
+                let mut message = format!("short object id {} is ambiguous\nhint: The candidates are:", name);
+                for oid in &candidates {
+                    let kind = self.read(oid).map(|(kind, _)| kind.as_str()).unwrap_or("unknown");
+                    message.push_str(&format!("\nhint:   {} {}", oid, kind));
+                }
+                Err(io::Error::new(io::ErrorKind::InvalidInput, message))
+            },
+        }
+    }
+
+    fn loose_with_prefix(&self, prefix: &str) -> io::Result<Vec<ObjectId>> {
+        let (dir, rest) = prefix.split_at(2);
+        let entries = match fs::read_dir(self.objects_dir.join(dir)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut oids = Vec::new();
+        for entry in entries {
+            let file_name = entry?.file_name().to_string_lossy().to_string();
+            if !file_name.starts_with(rest) {
+                continue;
+            }
+            let oid = format!("{}{}", dir, file_name).parse::<ObjectId>().ok();
+            if let Some(oid) = oid.filter(|oid| oid.format() == self.format) {
+                oids.push(oid);
+            }
+        }
+        Ok(oids)
+    }
+
+    pub fn contains(&self, oid: &ObjectId) -> bool {
+        self.contains_local(oid) || self.alternates.iter().any(|alternate| alternate.contains(oid))
+    }
+
+    // Whether this repository holds the object itself, not an alternate.
+    pub fn contains_local(&self, oid: &ObjectId) -> bool {
+        self.is_loose(oid) || self.packs().contains(&self.pack_dir(), self.format, oid).unwrap_or(false)
+    }
+
+    pub fn is_loose(&self, oid: &ObjectId) -> bool {
+        self.object_path(oid).is_file()
+    }
+
+    // When the loose copy of an object was last written.
+    pub fn loose_modified(&self, oid: &ObjectId) -> io::Result<SystemTime> {
+        fs::metadata(self.object_path(oid))?.modified()
+    }
+
+    // Delete the loose copy of an object, and its fan-out directory once empty.
+    pub fn remove_loose(&self, oid: &ObjectId) -> io::Result<()> {
+        let path = self.object_path(oid);
+        fs::remove_file(&path)?;
+
+        let dir = path.parent().expect("object path has a fan-out directory");
+        if fs::read_dir(dir)?.next().is_none() {
+            fs::remove_dir(dir)?;
+        }
+        Ok(())
+    }
+
+    // Every object id in the store, loose or packed, each listed once and
+    // in no particular order.
+    pub fn iter(&self) -> io::Result<impl Iterator<Item = ObjectId>> {
+        let mut oids = self.loose_oids()?;
+        let mut seen: HashSet<ObjectId> = oids.iter().copied().collect();
+        for oid in self.packed_oids()? {
+            if seen.insert(oid) {
+                oids.push(oid);
+            }
+        }
+
+        Ok(oids.into_iter())
+    }
+
+    pub fn packed_oids(&self) -> io::Result<Vec<ObjectId>> {
+        self.packs().oids(&self.pack_dir(), self.format)
+    }
+
+    pub fn loose_oids(&self) -> io::Result<Vec<ObjectId>> {
+        let mut oids = Vec::new();
+        for dir in fs::read_dir(&self.objects_dir)? {
+            let dir = dir?;
+            let dir_name = dir.file_name().to_string_lossy().to_string();
+            if dir_name.len() != 2 || !dir.path().is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(dir.path())? {
+                let file_name = file?.file_name().to_string_lossy().to_string();
+                let oid = format!("{}{}", dir_name, file_name).parse::<ObjectId>().ok();
+                if let Some(oid) = oid.filter(|oid| oid.format() == self.format) {
+                    oids.push(oid);
+                }
+            }
+        }
+
+        Ok(oids)
+    }
+}
+
+fn collect_alternates(objects_dir: &Path, format: ObjectFormat, visited: &mut HashSet<PathBuf>, alternates: &mut Vec<ObjectStore>) -> io::Result<()> {
+    let content = match fs::read_to_string(objects_dir.join("info").join("alternates")) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // Relative entries are relative to the objects directory listing them.
+        let path = objects_dir.join(line);
+        let Ok(canonical) = fs::canonicalize(&path) else {
+            eprintln!("warning: object directory {} does not exist; check {}", path.display(), objects_dir.join("info/alternates").display());
+            continue;
+        };
+        if !visited.insert(canonical.clone()) {
+            continue;
+        }
+
+        alternates.push(ObjectStore::new(canonical.clone(), format));
+        collect_alternates(&canonical, format, visited, alternates)?;
+    }
+    Ok(())
+}
+
+fn corrupt_object(oid: &ObjectId, reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("object {} is corrupt: {}", oid, reason))
+}
+
+// Parse the `<kind> <size>` header in front of a loose object's content.
+fn parse_loose_header(oid: &ObjectId, header: &[u8]) -> io::Result<(ObjectKind, usize)> {
+    let header = std::str::from_utf8(header).map_err(|_| corrupt_object(oid, "header is not valid UTF-8"))?;
+    let (kind, size) = header.split_once(' ').ok_or_else(|| corrupt_object(oid, "malformed header"))?;
+    let kind = kind.parse::<ObjectKind>()?;
+    let size: usize = size.parse().map_err(|_| corrupt_object(oid, "malformed object size"))?;
+    Ok((kind, size))
+}
+
+fn check_stream_size(expected: u64, actual: u64) -> io::Result<()> {
+    if expected != actual {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("expected {} bytes but read {}", expected, actual)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_matches_git() {
+        // `printf 'hello\n' | git hash-object --stdin`
+        let oid = ObjectFormat::Sha1.hash_object(ObjectKind::Blob, b"hello\n");
+        assert_eq!(oid.to_hex(), "ce013625030ba8dba906f756967f9e9ca394464a");
+    }
+
+    #[test]
+    fn test_write_read_round_trip() {
+        let dir = std::env::temp_dir().join(format!("warp-object-store-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let store = ObjectStore::new(dir.clone(), ObjectFormat::Sha1);
+
+        let oid = store.write(ObjectKind::Blob, b"hello\n").unwrap();
+        assert!(store.contains(&oid));
+        assert_eq!(store.read(&oid).unwrap(), (ObjectKind::Blob, b"hello\n".to_vec()));
+        assert_eq!(store.iter().unwrap().collect::<Vec<_>>(), vec![oid]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_stream_hash_and_write() {
+        let dir = std::env::temp_dir().join(format!("warp-object-stream-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let store = ObjectStore::new(dir.clone(), ObjectFormat::Sha1);
+
+        // Binary content spanning several read buffers.
+        let binary: Vec<u8> = (0..200_000u32).map(|n| (n % 251) as u8).collect();
+        let hashed = store.hash_stream(ObjectKind::Blob, binary.len() as u64, binary.as_slice()).unwrap();
+        assert_eq!(hashed, store.hash(ObjectKind::Blob, &binary));
+        assert!(!store.contains(&hashed));
+
+        let streamed = store.write_stream(ObjectKind::Blob, binary.len() as u64, binary.as_slice()).unwrap();
+        assert_eq!(streamed, hashed);
+        assert_eq!(store.read(&streamed).unwrap(), (ObjectKind::Blob, binary));
+
+        // The size goes into the header up front, so it must be right.
+        assert!(store.hash_stream(ObjectKind::Blob, 10, &b"short"[..]).is_err());
+        assert!(store.write_stream(ObjectKind::Blob, 10, &b"short"[..]).is_err());
+        assert_eq!(store.iter().unwrap().collect::<Vec<_>>(), vec![streamed]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_writes_are_idempotent() {
+        let dir = std::env::temp_dir().join(format!("warp-concurrent-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let store = ObjectStore::new(dir.clone(), ObjectFormat::Sha1);
+
+        let content: Vec<u8> = (0..100_000u32).map(|n| (n % 7) as u8).collect();
+        let oids: Vec<ObjectId> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8).map(|_| scope.spawn(|| store.write(ObjectKind::Blob, &content).unwrap())).collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+        assert!(oids.iter().all(|oid| *oid == oids[0]));
+        assert_eq!(store.read(&oids[0]).unwrap().1, content);
+
+        // Only the fan-out directory is left behind, no temporary files.
+        let leftovers: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(leftovers, vec![std::ffi::OsString::from(&oids[0].to_hex()[..2])]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_alternates_are_followed_without_looping() {
+        let dir = std::env::temp_dir().join(format!("warp-alternates-{}", std::process::id()));
+        let (first, second) = (dir.join("first"), dir.join("second"));
+        fs::create_dir_all(first.join("info")).unwrap();
+        fs::create_dir_all(second.join("info")).unwrap();
+        fs::write(first.join("info/alternates"), "../second\n").unwrap();
+        fs::write(second.join("info/alternates"), format!("{}\n", first.display())).unwrap();
+
+        let oid = ObjectStore::new(second.clone(), ObjectFormat::Sha1).write(ObjectKind::Blob, b"shared\n").unwrap();
+        let store = ObjectStore::new(first.clone(), ObjectFormat::Sha1).with_alternates().unwrap();
+        assert_eq!(store.alternates().len(), 1);
+        assert!(store.contains(&oid) && !store.contains_local(&oid));
+        assert_eq!(store.read(&oid).unwrap().1, b"shared\n");
+        assert_eq!(store.resolve(&oid.to_hex()[..6]).unwrap(), oid);
+
+        // Already present through the alternate, so nothing is written locally.
+        store.write(ObjectKind::Blob, b"shared\n").unwrap();
+        assert!(store.loose_oids().unwrap().is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_abbreviated_ids() {
+        let dir = std::env::temp_dir().join(format!("warp-resolve-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let store = ObjectStore::new(dir.clone(), ObjectFormat::Sha1);
+
+        // Find two blobs whose ids share their first four hex digits.
+        let mut seen = std::collections::HashMap::new();
+        let (first, second) = (0u32..).find_map(|n| {
+            let content = n.to_string().into_bytes();
+            let prefix = store.hash(ObjectKind::Blob, &content).to_hex()[..4].to_string();
+            seen.insert(prefix, content.clone()).map(|previous| (previous, content))
+        }).unwrap();
+        let first = store.write(ObjectKind::Blob, &first).unwrap();
+        let second = store.write(ObjectKind::Blob, &second).unwrap();
+
+        let hex = first.to_hex();
+        assert_eq!(store.resolve(&hex).unwrap(), first);
+        assert_eq!(store.resolve(&second.to_hex()[..12]).unwrap(), second);
+        let ambiguous = store.resolve(&hex[..4]).unwrap_err().to_string();
+        assert!(ambiguous.contains(&hex) && ambiguous.contains(&second.to_hex()));
+        assert!(store.resolve(&hex[..3]).is_err());
+        assert_eq!(store.resolve("deadbeef").unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
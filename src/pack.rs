@@ -0,0 +1,370 @@
+use std::{collections::VecDeque, fs, io::{self, BufWriter, Write}, path::{Path, PathBuf}, sync::atomic::{AtomicUsize, Ordering}};
+
+use flate2::{write::ZlibEncoder, Compression};
+
+use crate::delta::create_delta;
+use crate::object_id::{ObjectFormat, ObjectHasher, ObjectId};
+use crate::object_store::{ObjectKind, ObjectStore};
+use crate::reachable::ReachableObject;
+
+pub const PACK_SIGNATURE: &[u8; 4] = b"PACK";
+pub const IDX_SIGNATURE: &[u8; 4] = b"\xfftOc";
+pub const PACK_VERSION: u32 = 2;
+pub const IDX_VERSION: u32 = 2;
+pub const OFS_DELTA: u8 = 6;
+pub const REF_DELTA: u8 = 7;
+
+// Offsets at or above this value live in the idx's 64-bit offset table.
+const LARGE_OFFSET: u64 = 0x8000_0000;
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// The 3-bit type stored in every pack entry header.
+pub fn type_code(kind: ObjectKind) -> u8 {
+    match kind {
+        ObjectKind::Commit => 1,
+        ObjectKind::Tree => 2,
+        ObjectKind::Blob => 3,
+        ObjectKind::Tag => 4,
+    }
+}
+
+// Encode a pack entry header: type in bits 4-6 of the first byte, the size
+// as a little-endian base-128 varint spread over the remaining bits.
+pub fn encode_entry_header(type_code: u8, size: usize) -> Vec<u8> {
+    let mut size = size as u64;
+    let mut byte = (type_code << 4) | (size & 0x0f) as u8;
+    size >>= 4;
+
+    let mut header = Vec::new();
+    while size != 0 {
+        header.push(byte | 0x80);
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    header.push(byte);
+    header
+}
+
+// Where one object ended up in a pack, as recorded in the idx.
+#[derive(Clone, Debug)]
+pub struct IndexRecord {
+    pub oid: ObjectId,
+    pub offset: u64,
+    pub crc: u32
+}
+
+// A writer that hashes and counts everything passing through it, used for
+// the trailing checksum of packs and idx files.
+struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: ObjectHasher,
+    written: u64
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W, format: ObjectFormat) -> Self {
+        Self {
+            inner,
+            hasher: format.hasher(),
+            written: 0
+        }
+    }
+
+    // Append the running checksum and return it.
+    fn finish(mut self) -> io::Result<ObjectId> {
+        let checksum = self.hasher.finalize();
+        self.inner.write_all(checksum.as_bytes())?;
+        self.inner.flush()?;
+        Ok(checksum)
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn compress(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes)?;
+    encoder.finish()
+}
+
+// How hard the packer looks for delta bases: each object is compared with
+// up to `window` preceding objects of the same type, and no delta chain
+// grows longer than `depth`.
+#[derive(Clone, Copy, Debug)]
+pub struct PackOptions {
+    pub window: usize,
+    pub depth: usize
+}
+
+impl Default for PackOptions {
+    fn default() -> Self {
+        Self {
+            window: 10,
+            depth: 50
+        }
+    }
+}
+
+// Git's path hash: dominated by the last characters of the path, so files
+// with the same name or extension sort next to each other.
+pub fn name_hash(path: &str) -> u32 {
+    path.bytes()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .fold(0u32, |hash, byte| (hash >> 2).wrapping_add((byte as u32) << 24))
+}
+
+// Encode the distance back to an OFS_DELTA base: big-endian base-128 where
+// every continuation adds one before shifting.
+fn encode_base_distance(mut distance: u64) -> Vec<u8> {
+    let mut bytes = vec![(distance & 0x7f) as u8];
+    distance >>= 7;
+    while distance != 0 {
+        distance -= 1;
+        bytes.insert(0, 0x80 | (distance & 0x7f) as u8);
+        distance >>= 7;
+    }
+    bytes
+}
+
+// An already written object that later objects may delta against.
+struct WindowEntry {
+    kind: ObjectKind,
+    data: Vec<u8>,
+    offset: u64,
+    depth: usize
+}
+
+// Write `objects` into `objects/pack/pack-<checksum>.pack` together with its
+// `.idx`, returning the path of the new pack.
+pub fn write_pack(store: &ObjectStore, objects: &[ReachableObject], options: &PackOptions) -> io::Result<PathBuf> {
+    let pack_dir = store.objects_dir().join("pack");
+    fs::create_dir_all(&pack_dir)?;
+
+    // Names of their own, so that concurrent runs do not write into each
+    // other's files. The idx goes into place last, since readers only look
+    // at packs that have one.
+    let tmp_name = format!("tmp_pack_{}_{}", std::process::id(), TEMP_COUNTER.fetch_add(1, Ordering::Relaxed));
+    let tmp_pack = pack_dir.join(&tmp_name);
+    let tmp_idx = pack_dir.join(format!("{}_idx", tmp_name));
+    let result = (|| {
+        let (checksum, mut records) = write_pack_to(store, objects, options, BufWriter::new(fs::File::create(&tmp_pack)?))?;
+        write_idx(&tmp_idx, &mut records, &checksum)?;
+
+        let pack_path = pack_dir.join(format!("pack-{}.pack", checksum));
+        fs::rename(&tmp_pack, &pack_path)?;
+        fs::rename(&tmp_idx, pack_path.with_extension("idx"))?;
+        Ok(pack_path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_pack);
+        let _ = fs::remove_file(&tmp_idx);
+    }
+    result
+}
+
+// Stream a pack of `objects` into `out`, returning its checksum and where
+// each object landed. Objects are ordered by type, path hash and descending
+// size so that similar objects meet inside the delta window; a delta is kept
+// only when it is well under the full size.
+pub fn write_pack_to<W: Write>(store: &ObjectStore, objects: &[ReachableObject], options: &PackOptions, out: W) -> io::Result<(ObjectId, Vec<IndexRecord>)> {
+    let mut order = Vec::with_capacity(objects.len());
+    for (position, object) in objects.iter().enumerate() {
+        let (_, size) = store.read_header(&object.oid)?;
+        order.push((type_code(object.kind), name_hash(&object.path), std::cmp::Reverse(size), position));
+    }
+    order.sort();
+
+    let mut pack = HashingWriter::new(out, store.format());
+    pack.write_all(PACK_SIGNATURE)?;
+    pack.write_all(&PACK_VERSION.to_be_bytes())?;
+    pack.write_all(&(objects.len() as u32).to_be_bytes())?;
+
+    let mut window: VecDeque<WindowEntry> = VecDeque::with_capacity(options.window);
+    let mut records = Vec::with_capacity(objects.len());
+    for (_, _, _, position) in order {
+        let object = &objects[position];
+        let (kind, bytes) = store.read(&object.oid)?;
+        let offset = pack.written;
+
+        let mut best: Option<(Vec<u8>, &WindowEntry)> = None;
+        for candidate in window.iter().rev() {
+            if candidate.kind != kind || candidate.depth >= options.depth {
+                continue;
+            }
+            let limit = best.as_ref().map_or(bytes.len() / 2, |(delta, _)| delta.len());
+            if candidate.data.len().abs_diff(bytes.len()) >= limit {
+                continue;
+            }
+            let delta = create_delta(&candidate.data, &bytes);
+            if delta.len() < limit {
+                best = Some((delta, candidate));
+            }
+        }
+
+        let (entry, depth) = match best {
+            Some((delta, base)) => {
+                let mut entry = encode_entry_header(OFS_DELTA, delta.len());
+                entry.extend(encode_base_distance(offset - base.offset));
+                entry.extend(compress(&delta)?);
+                (entry, base.depth + 1)
+            },
+            None => {
+                let mut entry = encode_entry_header(type_code(kind), bytes.len());
+                entry.extend(compress(&bytes)?);
+                (entry, 0)
+            }
+        };
+
+        records.push(IndexRecord { oid: object.oid, offset, crc: crc32fast::hash(&entry) });
+        pack.write_all(&entry)?;
+
+        if options.window > 0 {
+            if window.len() == options.window {
+                window.pop_front();
+            }
+            window.push_back(WindowEntry { kind, data: bytes, offset, depth });
+        }
+    }
+    let checksum = pack.finish()?;
+
+    Ok((checksum, records))
+}
+
+// Write a version 2 idx: fan-out table, sorted object ids, CRC32s, 31-bit
+// offsets, 64-bit overflow offsets, then the pack and idx checksums.
+pub fn write_idx(path: &Path, records: &mut [IndexRecord], pack_checksum: &ObjectId) -> io::Result<()> {
+    records.sort_by_key(|record| record.oid);
+
+    let mut idx = HashingWriter::new(BufWriter::new(fs::File::create(path)?), pack_checksum.format());
+    idx.write_all(IDX_SIGNATURE)?;
+    idx.write_all(&IDX_VERSION.to_be_bytes())?;
+
+    let mut fanout = [0u32; 256];
+    for record in records.iter() {
+        fanout[record.oid.as_bytes()[0] as usize] += 1;
+    }
+    let mut running = 0;
+    for count in fanout.iter_mut() {
+        running += *count;
+        *count = running;
+    }
+    for count in fanout {
+        idx.write_all(&count.to_be_bytes())?;
+    }
+
+    for record in records.iter() {
+        idx.write_all(record.oid.as_bytes())?;
+    }
+    for record in records.iter() {
+        idx.write_all(&record.crc.to_be_bytes())?;
+    }
+
+    let mut large_offsets = Vec::new();
+    for record in records.iter() {
+        if record.offset < LARGE_OFFSET {
+            idx.write_all(&(record.offset as u32).to_be_bytes())?;
+        } else {
+            idx.write_all(&(0x8000_0000 | large_offsets.len() as u32).to_be_bytes())?;
+            large_offsets.push(record.offset);
+        }
+    }
+    for offset in large_offsets {
+        idx.write_all(&offset.to_be_bytes())?;
+    }
+
+    idx.write_all(pack_checksum.as_bytes())?;
+    idx.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pack_reader::Packs;
+
+    #[test]
+    fn test_entry_header() {
+        assert_eq!(encode_entry_header(3, 5), vec![0x35]);
+        // 300 = 0b1_0010_1100: low nibble 0xc, then 300 >> 4 = 18.
+        assert_eq!(encode_entry_header(1, 300), vec![0x9c, 0x12]);
+    }
+
+    #[test]
+    fn test_base_distance() {
+        assert_eq!(encode_base_distance(100), vec![100]);
+        // 128 = (0 + 1) << 7 | 0
+        assert_eq!(encode_base_distance(128), vec![0x80, 0x00]);
+    }
+
+    #[test]
+    fn test_write_pack_round_trip() {
+        let dir = std::env::temp_dir().join(format!("warp-write-pack-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let store = ObjectStore::new(dir.clone(), ObjectFormat::Sha1);
+
+        let text: Vec<u8> = (0..2000u32).flat_map(|n| format!("line {}\n", n).into_bytes()).collect();
+        let contents = [
+            (ObjectKind::Blob, text.clone()),
+            (ObjectKind::Blob, [&text[..], b"one more line\n"].concat()),
+            (ObjectKind::Blob, Vec::new()),
+            (ObjectKind::Commit, b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\nmessage\n".to_vec()),
+        ];
+        let objects: Vec<ReachableObject> = contents.iter()
+            .map(|(kind, bytes)| ReachableObject { oid: store.write(*kind, bytes).unwrap(), kind: *kind, path: "file".to_string() })
+            .collect();
+
+        let pack_path = write_pack(&store, &objects, &PackOptions::default()).unwrap();
+        let mut names: Vec<_> = fs::read_dir(store.pack_dir()).unwrap().map(|entry| entry.unwrap().path()).collect();
+        names.sort();
+        assert_eq!(names, vec![pack_path.with_extension("idx"), pack_path.clone()]);
+
+        let mut packs = Packs::default();
+        for (object, (kind, bytes)) in objects.iter().zip(&contents) {
+            assert_eq!(packs.read(&store.pack_dir(), store.format(), &object.oid).unwrap(), Some((*kind, bytes.clone())));
+        }
+        let missing = store.hash(ObjectKind::Blob, b"not packed");
+        assert_eq!(packs.read(&store.pack_dir(), store.format(), &missing).unwrap(), None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_object_headers() {
+        let dir = std::env::temp_dir().join(format!("warp-pack-headers-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let store = ObjectStore::new(dir.clone(), ObjectFormat::Sha1);
+
+        // Two similar blobs, so that the smaller one is stored as a delta.
+        let base: Vec<u8> = (0..5000u32).map(|n| (n % 97) as u8).collect();
+        let edited = [&base[..4000], b"edited"].concat();
+        let objects: Vec<ReachableObject> = [&base, &edited].iter()
+            .map(|bytes| ReachableObject { oid: store.write(ObjectKind::Blob, bytes).unwrap(), kind: ObjectKind::Blob, path: "file".to_string() })
+            .collect();
+        for object in &objects {
+            assert_eq!(store.read_header(&object.oid).unwrap(), (ObjectKind::Blob, store.read(&object.oid).unwrap().1.len()));
+        }
+
+        write_pack(&store, &objects, &PackOptions::default()).unwrap();
+        for object in &objects {
+            store.remove_loose(&object.oid).unwrap();
+        }
+        assert_eq!(store.read_header(&objects[0].oid).unwrap(), (ObjectKind::Blob, base.len()));
+        assert_eq!(store.read_header(&objects[1].oid).unwrap(), (ObjectKind::Blob, edited.len()));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
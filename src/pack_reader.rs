@@ -0,0 +1,649 @@
+use std::{collections::{HashMap, VecDeque}, fs, io::{self, BufRead, BufReader, Read, Seek, SeekFrom}, path::{Path, PathBuf}, time::{Duration, SystemTime}};
+
+use flate2::bufread::ZlibDecoder;
+
+use crate::delta::{apply_delta, target_size};
+use crate::object_id::{ObjectFormat, ObjectId};
+use crate::object_store::{ObjectKind, ObjectStore};
+use crate::pack::{IDX_SIGNATURE, IDX_VERSION, OFS_DELTA, PACK_SIGNATURE, REF_DELTA};
+
+// Upper bounds for the cache of reconstructed delta bases.
+const DELTA_CACHE_BYTES: usize = 32 * 1024 * 1024;
+const DELTA_CACHE_ENTRIES: usize = 256;
+// Sizes read from a pack are only trusted this far when reserving memory;
+// larger objects grow their buffer as the data actually arrives.
+const MAX_PREALLOCATION: usize = 1 << 20;
+// How long after a change of the pack directory its timestamp cannot yet
+// be trusted to move again on the next change.
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+// Longer delta chains than this are taken to be a loop in a corrupt pack.
+const MAX_DELTA_CHAIN: usize = 10_000;
+
+fn invalid_pack(path: &Path, reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), reason))
+}
+
+fn kind_from_code(code: u8) -> Option<ObjectKind> {
+    match code {
+        1 => Some(ObjectKind::Commit),
+        2 => Some(ObjectKind::Tree),
+        3 => Some(ObjectKind::Blob),
+        4 => Some(ObjectKind::Tag),
+        _ => None,
+    }
+}
+
+// A version 2 `.idx` file held in memory. Object ids and the trailing
+// checksums are as wide as the repository's object format.
+#[derive(Debug)]
+pub struct PackIndex {
+    data: Vec<u8>,
+    count: usize,
+    oid_len: usize
+}
+
+impl PackIndex {
+    pub fn open(path: &Path, format: ObjectFormat) -> io::Result<Self> {
+        let oid_len = format.raw_len();
+        let data = fs::read(path)?;
+        if data.len() < 8 + 256 * 4 + 2 * oid_len || &data[..4] != IDX_SIGNATURE {
+            return Err(invalid_pack(path, "not a version 2 pack index"));
+        }
+        let version = u32::from_be_bytes(data[4..8].try_into().unwrap());
+        if version != IDX_VERSION {
+            return Err(invalid_pack(path, &format!("unsupported index version {}", version)));
+        }
+
+        let count = u32::from_be_bytes(data[8 + 255 * 4..8 + 256 * 4].try_into().unwrap()) as usize;
+        // oids, crcs and 32-bit offsets, plus the two trailing checksums.
+        let minimum = 8 + 256 * 4 + count * (oid_len + 8) + 2 * oid_len;
+        if data.len() < minimum {
+            return Err(invalid_pack(path, "truncated pack index"));
+        }
+        // Whatever is left is the table of 64-bit offsets.
+        if !(data.len() - minimum).is_multiple_of(8) {
+            return Err(invalid_pack(path, "pack index has a partial large offset"));
+        }
+        let large_offsets = (data.len() - minimum) / 8;
+
+        let index = Self { data, count, oid_len };
+        // Every range the fan-out table hands to `find` must lie inside the
+        // object ids, and every large offset inside its table.
+        let mut previous = 0;
+        for byte in 0..256 {
+            let bound = index.fanout(byte);
+            if bound < previous {
+                return Err(invalid_pack(path, "pack index fan-out table is not sorted"));
+            }
+            previous = bound;
+        }
+        for position in 0..count {
+            let offset = index.small_offset_at(position);
+            if offset & 0x8000_0000 != 0 && (offset & 0x7fff_ffff) as usize >= large_offsets {
+                return Err(invalid_pack(path, &format!("pack index large offset {} is out of range", offset & 0x7fff_ffff)));
+            }
+        }
+
+        Ok(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    fn fanout(&self, byte: usize) -> usize {
+        let start = 8 + byte * 4;
+        u32::from_be_bytes(self.data[start..start + 4].try_into().unwrap()) as usize
+    }
+
+    pub fn oid_at(&self, position: usize) -> ObjectId {
+        let start = 8 + 256 * 4 + position * self.oid_len;
+        ObjectId::from_slice(&self.data[start..start + self.oid_len]).expect("slice has the width of an object id")
+    }
+
+    pub fn crc_at(&self, position: usize) -> u32 {
+        let start = 8 + 256 * 4 + self.count * self.oid_len + position * 4;
+        u32::from_be_bytes(self.data[start..start + 4].try_into().unwrap())
+    }
+
+    // The 31-bit offset, or the 64-bit table position when the top bit is set.
+    fn small_offset_at(&self, position: usize) -> u32 {
+        let start = 8 + 256 * 4 + self.count * (self.oid_len + 4) + position * 4;
+        u32::from_be_bytes(self.data[start..start + 4].try_into().unwrap())
+    }
+
+    pub fn offset_at(&self, position: usize) -> u64 {
+        let offset = self.small_offset_at(position);
+        if offset & 0x8000_0000 == 0 {
+            return offset as u64;
+        }
+
+        let large = 8 + 256 * 4 + self.count * (self.oid_len + 8) + (offset & 0x7fff_ffff) as usize * 8;
+        u64::from_be_bytes(self.data[large..large + 8].try_into().unwrap())
+    }
+
+    // Narrow the search with the fan-out table, then binary search the
+    // sorted object ids inside that range.
+    pub fn find(&self, oid: &ObjectId) -> Option<usize> {
+        let first = oid.as_bytes()[0] as usize;
+        let mut low = if first == 0 { 0 } else { self.fanout(first - 1) };
+        let mut high = self.fanout(first);
+
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.oid_at(mid).cmp(oid) {
+                std::cmp::Ordering::Equal => return Some(mid),
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+            }
+        }
+        None
+    }
+
+    pub fn oids(&self) -> impl Iterator<Item = ObjectId> + '_ {
+        (0..self.count).map(|position| self.oid_at(position))
+    }
+
+    // Ids starting with a lowercase hex `prefix` of at least two digits.
+    pub fn with_prefix(&self, prefix: &str) -> Vec<ObjectId> {
+        let Ok(first) = u8::from_str_radix(&prefix[..2], 16) else {
+            return Vec::new();
+        };
+        let first = first as usize;
+        let low = if first == 0 { 0 } else { self.fanout(first - 1) };
+        let high = self.fanout(first);
+
+        (low..high).map(|position| self.oid_at(position))
+            .filter(|oid| oid.to_hex().starts_with(prefix))
+            .collect()
+    }
+}
+
+// A `.pack` file and its index.
+#[derive(Debug)]
+pub struct Pack {
+    pack_path: PathBuf,
+    index: PackIndex,
+    format: ObjectFormat
+}
+
+// What sits at a given offset of a pack.
+enum RawEntry {
+    Full(ObjectKind, Vec<u8>),
+    OfsDelta(u64, Vec<u8>),
+    RefDelta(ObjectId, Vec<u8>)
+}
+
+// Where the base of a delta entry is: earlier in the same pack, or named by
+// its object id.
+enum DeltaBase {
+    Offset(u64),
+    Object(ObjectId)
+}
+
+// The same, with only the size of the object each entry stands for.
+enum RawHeader {
+    Full(ObjectKind, usize),
+    OfsDelta(u64, usize),
+    RefDelta(ObjectId, usize)
+}
+
+impl Pack {
+    pub fn open(idx_path: &Path, format: ObjectFormat) -> io::Result<Self> {
+        let index = PackIndex::open(idx_path, format)?;
+        let pack_path = idx_path.with_extension("pack");
+
+        let mut header = [0u8; 12];
+        fs::File::open(&pack_path)?.read_exact(&mut header)?;
+        if &header[..4] != PACK_SIGNATURE {
+            return Err(invalid_pack(&pack_path, "bad pack signature"));
+        }
+        let version = u32::from_be_bytes(header[4..8].try_into().unwrap());
+        if version != 2 && version != 3 {
+            return Err(invalid_pack(&pack_path, &format!("unsupported pack version {}", version)));
+        }
+
+        Ok(Self { pack_path, index, format })
+    }
+
+    pub fn pack_path(&self) -> &Path {
+        &self.pack_path
+    }
+
+    pub fn index(&self) -> &PackIndex {
+        &self.index
+    }
+
+    fn read_raw(&self, offset: u64) -> io::Result<RawEntry> {
+        let mut file = fs::File::open(&self.pack_path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        read_entry(&mut BufReader::new(file), offset, self.format, &self.pack_path)
+    }
+
+    // Read an entry's header, inflating a delta only as far as the target
+    // size at its start.
+    fn read_raw_header(&self, offset: u64) -> io::Result<RawHeader> {
+        let mut file = fs::File::open(&self.pack_path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut reader = BufReader::new(file);
+        let (type_code, size, base) = read_entry_header(&mut reader, offset, self.format, &self.pack_path)?;
+
+        let Some(base) = base else {
+            let kind = kind_from_code(type_code).ok_or_else(|| invalid_pack(&self.pack_path, &format!("unknown entry type {}", type_code)))?;
+            return Ok(RawHeader::Full(kind, size));
+        };
+        // Two base-128 sizes of at most ten bytes each.
+        let mut sizes = Vec::with_capacity(20);
+        ZlibDecoder::new(reader).take(20).read_to_end(&mut sizes)?;
+        let target = target_size(&sizes)?;
+        Ok(match base {
+            DeltaBase::Offset(base_offset) => RawHeader::OfsDelta(base_offset, target),
+            DeltaBase::Object(base_oid) => RawHeader::RefDelta(base_oid, target),
+        })
+    }
+}
+
+// Parse the entry starting at `offset`, leaving `reader` just past its
+// compressed data.
+fn read_entry<R: BufRead>(reader: &mut R, offset: u64, format: ObjectFormat, path: &Path) -> io::Result<RawEntry> {
+    let (type_code, size, base) = read_entry_header(reader, offset, format, path)?;
+
+    let mut data = Vec::with_capacity(size.min(MAX_PREALLOCATION));
+    ZlibDecoder::new(reader).read_to_end(&mut data)?;
+    if data.len() != size {
+        return Err(invalid_pack(path, &format!("entry at offset {} has the wrong size", offset)));
+    }
+
+    Ok(match base {
+        Some(DeltaBase::Offset(base_offset)) => RawEntry::OfsDelta(base_offset, data),
+        Some(DeltaBase::Object(base_oid)) => RawEntry::RefDelta(base_oid, data),
+        None => {
+            let kind = kind_from_code(type_code).ok_or_else(|| invalid_pack(path, &format!("unknown entry type {}", type_code)))?;
+            RawEntry::Full(kind, data)
+        }
+    })
+}
+
+// Parse the type, inflated size and delta base of the entry at `offset`,
+// leaving `reader` at the start of its compressed data.
+fn read_entry_header<R: BufRead>(reader: &mut R, offset: u64, format: ObjectFormat, path: &Path) -> io::Result<(u8, usize, Option<DeltaBase>)> {
+    let mut byte = [0u8; 1];
+
+    reader.read_exact(&mut byte)?;
+    let type_code = (byte[0] >> 4) & 0x07;
+    let mut size = (byte[0] & 0x0f) as usize;
+    let mut shift = 4;
+    while byte[0] & 0x80 != 0 {
+        // Corrupt or hostile packs must not shift bits past the end.
+        if shift > usize::BITS - 7 {
+            return Err(invalid_pack(path, "entry size is too large"));
+        }
+        reader.read_exact(&mut byte)?;
+        size |= ((byte[0] & 0x7f) as usize) << shift;
+        shift += 7;
+    }
+
+    let base = match type_code {
+        OFS_DELTA => {
+            reader.read_exact(&mut byte)?;
+            let mut distance = (byte[0] & 0x7f) as u64;
+            while byte[0] & 0x80 != 0 {
+                reader.read_exact(&mut byte)?;
+                distance = distance.checked_add(1).filter(|distance| distance.leading_zeros() >= 7)
+                    .map(|distance| (distance << 7) | (byte[0] & 0x7f) as u64)
+                    .ok_or_else(|| invalid_pack(path, "delta base offset is too large"))?;
+            }
+            let base_offset = offset.checked_sub(distance).ok_or_else(|| invalid_pack(path, "delta base before start of pack"))?;
+            Some(DeltaBase::Offset(base_offset))
+        },
+        REF_DELTA => {
+            let mut base = vec![0u8; format.raw_len()];
+            reader.read_exact(&mut base)?;
+            Some(DeltaBase::Object(ObjectId::from_slice(&base).expect("read the width of an object id")))
+        },
+        _ => None,
+    };
+
+    Ok((type_code, size, base))
+}
+
+// Explode a pack that has no idx (for example one received in a bundle)
+// into loose objects. Deltas may use bases from the store as well as from
+// the pack itself. Returns the number of objects written.
+pub fn unpack_objects(pack: &[u8], path: &Path, store: &ObjectStore) -> io::Result<usize> {
+    let width = store.format().raw_len();
+    if pack.len() < 12 + width || &pack[..4] != PACK_SIGNATURE {
+        return Err(invalid_pack(path, "bad pack signature"));
+    }
+    let version = u32::from_be_bytes(pack[4..8].try_into().unwrap());
+    if version != 2 && version != 3 {
+        return Err(invalid_pack(path, &format!("unsupported pack version {}", version)));
+    }
+    let (content, trailer) = pack.split_at(pack.len() - width);
+    if store.format().digest(content).as_bytes() != trailer {
+        return Err(invalid_pack(path, "pack checksum mismatch"));
+    }
+
+    let count = u32::from_be_bytes(pack[8..12].try_into().unwrap()) as usize;
+    let mut remaining = &content[12..];
+    let mut entries = Vec::with_capacity(count.min(MAX_PREALLOCATION));
+    for _ in 0..count {
+        let offset = (content.len() - remaining.len()) as u64;
+        entries.push((offset, read_entry(&mut remaining, offset, store.format(), path)?));
+    }
+    if !remaining.is_empty() {
+        return Err(invalid_pack(path, "garbage after the last entry"));
+    }
+
+    // Resolve full objects first, then deltas whose bases are known, until
+    // nothing changes.
+    let mut resolved: HashMap<u64, (ObjectKind, Vec<u8>)> = HashMap::new();
+    let mut offsets: HashMap<ObjectId, u64> = HashMap::new();
+    let mut pending: Vec<usize> = (0..entries.len()).collect();
+    while !pending.is_empty() {
+        let before = pending.len();
+        let mut unresolved = Vec::new();
+        for position in pending {
+            let (offset, entry) = &entries[position];
+            let object = match entry {
+                RawEntry::Full(kind, data) => Some((*kind, data.clone())),
+                RawEntry::OfsDelta(base_offset, delta) => match resolved.get(base_offset) {
+                    Some((kind, base)) => Some((*kind, apply_delta(base, delta)?)),
+                    None => None,
+                },
+                RawEntry::RefDelta(base_oid, delta) => match offsets.get(base_oid).and_then(|base_offset| resolved.get(base_offset)) {
+                    Some((kind, base)) => Some((*kind, apply_delta(base, delta)?)),
+                    None if store.contains(base_oid) => {
+                        let (kind, base) = store.read(base_oid)?;
+                        Some((kind, apply_delta(&base, delta)?))
+                    },
+                    None => None,
+                },
+            };
+            match object {
+                Some((kind, data)) => {
+                    let oid = store.write(kind, &data)?;
+                    offsets.insert(oid, *offset);
+                    resolved.insert(*offset, (kind, data));
+                },
+                None => unresolved.push(position),
+            }
+        }
+        if unresolved.len() == before {
+            return Err(invalid_pack(path, &format!("{} deltas have missing bases", before)));
+        }
+        pending = unresolved;
+    }
+
+    Ok(entries.len())
+}
+
+// A small FIFO cache of reconstructed objects keyed by pack and offset, so
+// that long delta chains sharing a base do not re-inflate it every time.
+#[derive(Debug, Default)]
+struct DeltaBaseCache {
+    entries: HashMap<(usize, u64), (ObjectKind, Vec<u8>)>,
+    order: VecDeque<(usize, u64)>,
+    bytes: usize
+}
+
+impl DeltaBaseCache {
+    fn get(&self, key: &(usize, u64)) -> Option<(ObjectKind, Vec<u8>)> {
+        self.entries.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: (usize, u64), kind: ObjectKind, data: &[u8]) {
+        if data.len() > DELTA_CACHE_BYTES || self.entries.contains_key(&key) {
+            return;
+        }
+        while self.bytes + data.len() > DELTA_CACHE_BYTES || self.entries.len() >= DELTA_CACHE_ENTRIES {
+            let Some(oldest) = self.order.pop_front() else { break };
+            if let Some((_, evicted)) = self.entries.remove(&oldest) {
+                self.bytes -= evicted.len();
+            }
+        }
+        self.bytes += data.len();
+        self.order.push_back(key);
+        self.entries.insert(key, (kind, data.to_vec()));
+    }
+}
+
+// Every pack under `objects/pack`, loaded lazily and rescanned when an
+// object cannot be found and the directory has changed since the last scan
+// (another process may have repacked meanwhile).
+#[derive(Debug, Default)]
+pub struct Packs {
+    packs: Vec<Pack>,
+    loaded: bool,
+    scanned: Option<SystemTime>,
+    racy: bool,
+    cache: DeltaBaseCache
+}
+
+// When files were last added to or removed from the pack directory.
+fn pack_dir_modified(pack_dir: &Path) -> io::Result<Option<SystemTime>> {
+    match fs::metadata(pack_dir) {
+        Ok(metadata) => Ok(Some(metadata.modified()?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+impl Packs {
+    pub fn reload(&mut self, pack_dir: &Path, format: ObjectFormat) -> io::Result<()> {
+        self.packs.clear();
+        self.cache = DeltaBaseCache::default();
+        self.loaded = true;
+        // Taken before listing, so that a pack arriving meanwhile still
+        // triggers the next rescan. A directory changed within the last
+        // moments may change again without its coarse timestamp moving, so
+        // such a scan is not trusted.
+        self.scanned = pack_dir_modified(pack_dir)?;
+        self.racy = self.scanned.is_some_and(|modified| {
+            SystemTime::now().duration_since(modified).map_or(true, |age| age < RACY_WINDOW)
+        });
+
+        let entries = match fs::read_dir(pack_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        let mut idx_paths = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) == Some("idx") && path.with_extension("pack").is_file() {
+                idx_paths.push(path);
+            }
+        }
+        idx_paths.sort();
+
+        for idx_path in idx_paths {
+            self.packs.push(Pack::open(&idx_path, format)?);
+        }
+        Ok(())
+    }
+
+    fn ensure_loaded(&mut self, pack_dir: &Path, format: ObjectFormat) -> io::Result<()> {
+        if !self.loaded {
+            self.reload(pack_dir, format)?;
+        }
+        Ok(())
+    }
+
+    // Rescan only if packs were added or removed since the last scan, so
+    // that looking up absent objects does not re-read every idx each time.
+    fn refresh(&mut self, pack_dir: &Path, format: ObjectFormat) -> io::Result<()> {
+        if !self.loaded || self.racy || pack_dir_modified(pack_dir)? != self.scanned {
+            self.reload(pack_dir, format)?;
+        }
+        Ok(())
+    }
+
+    fn locate(&self, oid: &ObjectId) -> Option<(usize, u64)> {
+        self.packs.iter().enumerate().find_map(|(pack, p)| {
+            p.index.find(oid).map(|position| (pack, p.index.offset_at(position)))
+        })
+    }
+
+    fn locate_or_reload(&mut self, pack_dir: &Path, format: ObjectFormat, oid: &ObjectId) -> io::Result<Option<(usize, u64)>> {
+        self.ensure_loaded(pack_dir, format)?;
+        if let Some(location) = self.locate(oid) {
+            return Ok(Some(location));
+        }
+        self.refresh(pack_dir, format)?;
+        Ok(self.locate(oid))
+    }
+
+    pub fn contains(&mut self, pack_dir: &Path, format: ObjectFormat, oid: &ObjectId) -> io::Result<bool> {
+        Ok(self.locate_or_reload(pack_dir, format, oid)?.is_some())
+    }
+
+    // Like `contains`, but without rescanning the pack directory on a miss.
+    pub fn contains_loaded(&mut self, pack_dir: &Path, format: ObjectFormat, oid: &ObjectId) -> io::Result<bool> {
+        self.ensure_loaded(pack_dir, format)?;
+        Ok(self.locate(oid).is_some())
+    }
+
+    pub fn read(&mut self, pack_dir: &Path, format: ObjectFormat, oid: &ObjectId) -> io::Result<Option<(ObjectKind, Vec<u8>)>> {
+        match self.locate_or_reload(pack_dir, format, oid)? {
+            Some((pack, offset)) => self.read_at(pack, offset).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // The kind and size of a packed object. The size is in the header of
+    // the object's own entry; the kind is only in the entry at the bottom of
+    // its delta chain.
+    pub fn read_header(&mut self, pack_dir: &Path, format: ObjectFormat, oid: &ObjectId) -> io::Result<Option<(ObjectKind, usize)>> {
+        let Some(mut location) = self.locate_or_reload(pack_dir, format, oid)? else {
+            return Ok(None);
+        };
+
+        let mut size = None;
+        for _ in 0..=MAX_DELTA_CHAIN {
+            match self.packs[location.0].read_raw_header(location.1)? {
+                RawHeader::Full(kind, full_size) => return Ok(Some((kind, size.unwrap_or(full_size)))),
+                RawHeader::OfsDelta(base_offset, target) => {
+                    size.get_or_insert(target);
+                    location = (location.0, base_offset);
+                },
+                RawHeader::RefDelta(base_oid, target) => {
+                    size.get_or_insert(target);
+                    location = self.locate(&base_oid).ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::NotFound, format!("delta base {} is missing", base_oid))
+                    })?;
+                },
+            }
+        }
+        Err(invalid_pack(self.packs[location.0].pack_path(), "delta chain is too long"))
+    }
+
+    // Follow a delta chain down to its full base object, then apply the
+    // deltas back up, caching each intermediate result.
+    fn read_at(&mut self, pack: usize, offset: u64) -> io::Result<(ObjectKind, Vec<u8>)> {
+        let mut chain: Vec<((usize, u64), Vec<u8>)> = Vec::new();
+        let mut location = (pack, offset);
+
+        let (kind, mut data) = loop {
+            if let Some(cached) = self.cache.get(&location) {
+                break cached;
+            }
+            match self.packs[location.0].read_raw(location.1)? {
+                RawEntry::Full(kind, data) => break (kind, data),
+                RawEntry::OfsDelta(base_offset, delta) => {
+                    chain.push((location, delta));
+                    location = (location.0, base_offset);
+                },
+                RawEntry::RefDelta(base_oid, delta) => {
+                    chain.push((location, delta));
+                    location = self.locate(&base_oid).ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::NotFound, format!("delta base {} is missing", base_oid))
+                    })?;
+                },
+            }
+            if chain.len() > MAX_DELTA_CHAIN {
+                return Err(invalid_pack(self.packs[pack].pack_path(), "delta chain is too long"));
+            }
+        };
+
+        if !chain.is_empty() {
+            self.cache.insert(location, kind, &data);
+        }
+        while let Some((delta_location, delta)) = chain.pop() {
+            data = apply_delta(&data, &delta)?;
+            if !chain.is_empty() {
+                self.cache.insert(delta_location, kind, &data);
+            }
+        }
+
+        Ok((kind, data))
+    }
+
+    pub fn with_prefix(&mut self, pack_dir: &Path, format: ObjectFormat, prefix: &str) -> io::Result<Vec<ObjectId>> {
+        self.refresh(pack_dir, format)?;
+        Ok(self.packs.iter().flat_map(|pack| pack.index.with_prefix(prefix)).collect())
+    }
+
+    pub fn oids(&mut self, pack_dir: &Path, format: ObjectFormat) -> io::Result<Vec<ObjectId>> {
+        self.refresh(pack_dir, format)?;
+        Ok(self.packs.iter().flat_map(|pack| pack.index.oids()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pack::{write_pack, PackOptions};
+    use crate::reachable::ReachableObject;
+
+    #[test]
+    fn test_corrupt_index_is_rejected() {
+        let dir = std::env::temp_dir().join(format!("warp-corrupt-idx-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let store = ObjectStore::new(dir.clone(), ObjectFormat::Sha1);
+        let objects: Vec<ReachableObject> = [&b"one\n"[..], b"two\n", b"three\n"].iter()
+            .map(|bytes| ReachableObject { oid: store.write(ObjectKind::Blob, bytes).unwrap(), kind: ObjectKind::Blob, path: "file".to_string() })
+            .collect();
+        let idx_path = write_pack(&store, &objects, &PackOptions::default()).unwrap().with_extension("idx");
+        let good = fs::read(&idx_path).unwrap();
+        assert_eq!(PackIndex::open(&idx_path, store.format()).unwrap().len(), 3);
+
+        let open_with = |change: &dyn Fn(&mut Vec<u8>)| {
+            let mut bytes = good.clone();
+            change(&mut bytes);
+            fs::write(&idx_path, bytes).unwrap();
+            PackIndex::open(&idx_path, store.format()).unwrap_err().kind()
+        };
+        // A fan-out entry larger than the one after it.
+        assert_eq!(open_with(&|bytes| bytes[8..12].copy_from_slice(&3u32.to_be_bytes())), io::ErrorKind::InvalidData);
+        // A 64-bit offset that the (empty) large offset table does not hold.
+        let offsets = 8 + 256 * 4 + 3 * (20 + 4);
+        assert_eq!(open_with(&|bytes| bytes[offsets..offsets + 4].copy_from_slice(&0x8000_0000u32.to_be_bytes())), io::ErrorKind::InvalidData);
+        // Trailing bytes that are not a whole large offset.
+        assert_eq!(open_with(&|bytes| bytes.push(0)), io::ErrorKind::InvalidData);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_oversized_entry_header_is_rejected() {
+        let read = |bytes: &[u8]| read_entry_header(&mut io::Cursor::new(bytes), 1 << 20, ObjectFormat::Sha1, Path::new("test.pack"))
+            .map(|(type_code, size, base)| (type_code, size, base.is_some()));
+        assert_eq!(read(&[0xb5, 0x02]).unwrap(), (3, 0x25, false));
+        // A blob whose size keeps going past 64 bits.
+        let mut size = vec![0xb0];
+        size.extend([0xff; 10]);
+        size.push(0x01);
+        assert_eq!(read(&size).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        // An offset delta whose base distance keeps growing.
+        let mut distance = vec![0x60];
+        distance.extend([0xff; 10]);
+        distance.push(0x00);
+        assert_eq!(read(&distance).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
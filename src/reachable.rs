@@ -0,0 +1,167 @@
+use std::{collections::HashSet, io, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::chunking::Manifest;
+use crate::index::{read_index, CacheTreeEntry, WarpIndex};
+use crate::object::Object;
+use crate::object_id::ObjectId;
+use crate::object_store::{ObjectKind, ObjectStore};
+use crate::refs::Refs;
+
+// An object found while walking the object graph, along with the path it
+// was first seen at (empty for commits, tags and root trees).
+#[derive(Clone, Debug)]
+pub struct ReachableObject {
+    pub oid: ObjectId,
+    pub kind: ObjectKind,
+    pub path: String
+}
+
+// Every object id the repository still cares about: all refs, HEAD, the
+// reflogs and the blobs and cached trees recorded in the index.
+pub fn repository_roots(refs: &Refs) -> io::Result<Vec<ObjectId>> {
+    let mut roots: Vec<ObjectId> = refs.list()?.into_iter().map(|(_, oid)| oid).collect();
+    roots.extend(refs.read_head()?);
+    roots.extend(refs.reflog_oids()?);
+
+    if let Some(index) = read_index()? {
+        collect_index_roots(&index, &mut roots);
+    }
+
+    Ok(roots)
+}
+
+// The staged blobs and cached trees of an index. Gitlinks name commits of
+// another repository, which this store does not hold.
+pub(crate) fn collect_index_roots(index: &WarpIndex, roots: &mut Vec<ObjectId>) {
+    roots.extend(index.entries.iter().filter(|entry| entry.mode != 0o160000).map(|entry| entry.sha));
+    if let Some(extension) = &index.extensions {
+        collect_cache_trees(&extension.extension_data, roots);
+    }
+}
+
+fn collect_cache_trees(entry: &CacheTreeEntry, roots: &mut Vec<ObjectId>) {
+    if entry.is_valid() {
+        roots.push(entry.sha);
+    }
+    for subtree in entry.subtrees.iter().flatten() {
+        collect_cache_trees(subtree, roots);
+    }
+}
+
+// Walk commits, trees and tags from `roots` and return every object reached,
+// each exactly once. Fails if a referenced object is missing from the store.
+pub fn walk(store: &ObjectStore, roots: &[ObjectId]) -> io::Result<Vec<ReachableObject>> {
+    let mut seen = HashSet::new();
+    let mut found = Vec::new();
+    // (object, path, whether it was reached as a chunk of a manifest)
+    let mut pending: Vec<(ObjectId, String, bool)> = roots.iter().rev().map(|oid| (*oid, String::new(), false)).collect();
+
+    while let Some((oid, path, chunk)) = pending.pop() {
+        // Chunks are raw content and never read as manifests, even when the
+        // same blob is also reached as a file.
+        if !seen.insert((oid, chunk)) {
+            continue;
+        }
+        let first = !seen.contains(&(oid, !chunk));
+
+        let (kind, bytes) = store.read(&oid)?;
+        match Object::parse(kind, &bytes, store.format())? {
+            Object::Blob(_) => {
+                // Chunked content is only reachable through its manifest.
+                if !chunk {
+                    if let Some(manifest) = Manifest::detect(&bytes).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", oid, e)))? {
+                        pending.extend(manifest.chunks.iter().rev().map(|(chunk, _)| (*chunk, path.clone(), true)));
+                    }
+                }
+            },
+            Object::Tree(tree) => {
+                for entry in tree.entries.iter().rev() {
+                    // Submodule commits live in another repository.
+                    if entry.kind() == ObjectKind::Commit {
+                        continue;
+                    }
+                    // Only used to group similar objects when packing.
+                    let name = String::from_utf8_lossy(&entry.name);
+                    let entry_path = if path.is_empty() { name.to_string() } else { format!("{}/{}", path, name) };
+                    pending.push((entry.oid, entry_path, false));
+                }
+            },
+            Object::Commit(commit) => {
+                pending.extend(commit.parents.iter().rev().map(|parent| (*parent, String::new(), false)));
+                pending.push((commit.tree, String::new(), false));
+            },
+            Object::Tag(tag) => pending.push((tag.object, String::new(), false)),
+        }
+
+        if !first {
+            continue;
+        }
+        found.push(ReachableObject { oid, kind, path });
+    }
+
+    Ok(found)
+}
+
+// Whether a file last modified at `modified` is at or past the expiry date
+// `expire`, in seconds since the epoch.
+pub fn is_expired(modified: SystemTime, expire: i64) -> bool {
+    let modified = modified.duration_since(UNIX_EPOCH).map(|age| age.as_secs() as i64).unwrap_or(0);
+    modified <= expire
+}
+
+// Delete the loose objects that nothing in `roots` reaches and that were
+// last written at or before `expire`, returning their ids. With `dry_run`
+// they are only listed.
+pub fn prune_unreachable(store: &ObjectStore, roots: &[ObjectId], expire: i64, dry_run: bool) -> io::Result<Vec<ObjectId>> {
+    let reachable: HashSet<ObjectId> = walk(store, roots)?.into_iter().map(|object| object.oid).collect();
+
+    let mut pruned = Vec::new();
+    for oid in store.loose_oids()? {
+        if reachable.contains(&oid) || !is_expired(store.loose_modified(&oid)?, expire) {
+            continue;
+        }
+        if !dry_run {
+            store.remove_loose(&oid)?;
+        }
+        pruned.push(oid);
+    }
+    Ok(pruned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auxiliary::parse_expiry;
+    use crate::index::IndexEntry;
+    use crate::object_id::ObjectFormat;
+    use std::fs;
+
+    #[test]
+    fn test_prune_keeps_reachable_and_recent_objects() {
+        let dir = std::env::temp_dir().join(format!("warp-prune-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let store = ObjectStore::new(dir.clone(), ObjectFormat::Sha1);
+        let staged = store.write(ObjectKind::Blob, b"staged\n").unwrap();
+        let unreachable = store.write(ObjectKind::Blob, b"unreachable\n").unwrap();
+        let submodule: ObjectId = "1111111111111111111111111111111111111111".parse().unwrap();
+
+        let index = WarpIndex::without_extension(vec![
+            IndexEntry::staged("file.txt".to_string(), 0o100644, staged, 0),
+            IndexEntry::staged("sub".to_string(), 0o160000, submodule, 0),
+        ], ObjectFormat::Sha1);
+        let mut roots = Vec::new();
+        collect_index_roots(&index, &mut roots);
+        assert_eq!(roots, vec![staged]);
+
+        let now = chrono::Local::now().timestamp();
+        let two_weeks_ago = parse_expiry("2.weeks.ago", now).unwrap().unwrap();
+        assert!(prune_unreachable(&store, &roots, two_weeks_ago, false).unwrap().is_empty());
+        assert_eq!(prune_unreachable(&store, &roots, now, true).unwrap(), vec![unreachable]);
+        assert!(store.contains(&unreachable));
+        assert_eq!(prune_unreachable(&store, &roots, now, false).unwrap(), vec![unreachable]);
+        assert!(!store.contains(&unreachable));
+        assert!(store.contains(&staged));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
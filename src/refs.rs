@@ -0,0 +1,301 @@
+use std::{fs, io, path::{Path, PathBuf}};
+
+use crate::auxiliary::warp_dir;
+use crate::object_id::ObjectId;
+use crate::object_store::ObjectStore;
+
+// Branches and tags stored as files under `.warp/refs`, plus the optional
+// `packed-refs` file and the `HEAD` pointer.
+#[derive(Clone, Debug)]
+pub struct Refs {
+    warp_dir: PathBuf
+}
+
+impl Refs {
+    pub fn new(warp_dir: PathBuf) -> Self {
+        Self {
+            warp_dir
+        }
+    }
+
+    pub fn open() -> Self {
+        Self::new(warp_dir())
+    }
+
+    // Every ref with its target, sorted by name. Loose refs win over
+    // entries of the same name in `packed-refs`.
+    pub fn list(&self) -> io::Result<Vec<(String, ObjectId)>> {
+        let mut refs = self.packed()?;
+
+        let mut loose = Vec::new();
+        collect_loose(&self.warp_dir, &self.warp_dir.join("refs"), &mut loose)?;
+        for (name, oid) in loose {
+            refs.retain(|(packed_name, _)| *packed_name != name);
+            refs.push((name, oid));
+        }
+
+        refs.sort();
+        Ok(refs)
+    }
+
+    fn packed(&self) -> io::Result<Vec<(String, ObjectId)>> {
+        let packed_refs = match fs::read_to_string(self.warp_dir.join("packed-refs")) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut refs = Vec::new();
+        for line in packed_refs.lines() {
+            // Comments and peeled `^<oid>` lines carry nothing we need.
+            if line.starts_with('#') || line.starts_with('^') {
+                continue;
+            }
+            if let Some((oid, name)) = line.split_once(' ') {
+                refs.push((name.to_string(), oid.parse()?));
+            }
+        }
+        Ok(refs)
+    }
+
+    // The commit HEAD points at, following a symbolic `ref: ` if present.
+    // Returns `None` for an unborn branch or an empty HEAD file.
+    pub fn read_head(&self) -> io::Result<Option<ObjectId>> {
+        self.resolve("HEAD")
+    }
+
+    // Resolve `HEAD`, a full ref name, or a short branch or tag name.
+    pub fn resolve(&self, name: &str) -> io::Result<Option<ObjectId>> {
+        let candidates = [
+            name.to_string(),
+            format!("refs/{}", name),
+            format!("refs/tags/{}", name),
+            format!("refs/heads/{}", name),
+        ];
+
+        for candidate in candidates {
+            let path = self.warp_dir.join(&candidate);
+            if path.is_file() {
+                let content = fs::read_to_string(&path)?;
+                let content = content.trim();
+                if content.is_empty() {
+                    return Ok(None);
+                }
+                return match content.strip_prefix("ref: ") {
+                    Some(target) => self.resolve(target),
+                    None => Ok(Some(content.parse()?)),
+                };
+            }
+            if let Some((_, oid)) = self.packed()?.into_iter().find(|(packed_name, _)| *packed_name == candidate) {
+                return Ok(Some(oid));
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Every object id recorded in the reflogs under `.warp/logs`, old and
+    // new values alike. Null ids from ref creation and deletion are skipped.
+    pub fn reflog_oids(&self) -> io::Result<Vec<ObjectId>> {
+        let mut files = Vec::new();
+        collect_files(&self.warp_dir.join("logs"), &mut files)?;
+
+        let mut oids = Vec::new();
+        for file in files {
+            for line in fs::read_to_string(file)?.lines() {
+                let ids = line.split(' ').take(2).filter_map(|id| id.parse::<ObjectId>().ok());
+                oids.extend(ids.filter(|oid| oid.as_bytes().iter().any(|&b| b != 0)));
+            }
+        }
+        Ok(oids)
+    }
+
+    // Point a ref such as `HEAD` at another ref with `ref: <target>`.
+    pub fn update_symbolic(&self, name: &str, target: &str) -> io::Result<()> {
+        fs::write(self.warp_dir.join(name), format!("ref: {}\n", target))
+    }
+
+    // Remove a ref, both its loose file and any `packed-refs` entry. Returns
+    // the id it pointed at, or `None` if there was no such ref. Only names
+    // under `refs/` are accepted, and a loose file is removed only if it
+    // actually holds a ref, so a bad name can never delete anything else.
+    pub fn delete(&self, name: &str) -> io::Result<Option<ObjectId>> {
+        check_ref_name(name)?;
+        if !name.starts_with("refs/") {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("'{}' is not under refs/", name)));
+        }
+        let mut previous = None;
+
+        let path = self.warp_dir.join(name);
+        if path.is_file() {
+            let content = fs::read_to_string(&path)?;
+            let content = content.trim();
+            if content.strip_prefix("ref: ").is_none() {
+                let oid = content.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("{} does not hold a ref", name)))?;
+                previous = Some(oid);
+            }
+            fs::remove_file(&path)?;
+        }
+
+        let packed_path = self.warp_dir.join("packed-refs");
+        if let Some((_, oid)) = self.packed()?.into_iter().find(|(packed_name, _)| packed_name == name) {
+            previous = previous.or(Some(oid));
+
+            // Drop the entry together with the peeled `^<oid>` line after it.
+            let content = fs::read_to_string(&packed_path)?;
+            let mut kept = String::new();
+            let mut dropping = false;
+            for line in content.lines() {
+                if line.starts_with('^') && dropping {
+                    continue;
+                }
+                dropping = line.split_once(' ').is_some_and(|(_, packed_name)| packed_name == name);
+                if !dropping {
+                    kept.push_str(line);
+                    kept.push('\n');
+                }
+            }
+            fs::write(&packed_path, kept)?;
+        }
+
+        Ok(previous)
+    }
+
+    pub fn update(&self, name: &str, oid: &ObjectId) -> io::Result<()> {
+        let path = self.warp_dir.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, format!("{}\n", oid))
+    }
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn collect_loose(warp_dir: &Path, dir: &Path, refs: &mut Vec<(String, ObjectId)>) -> io::Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_loose(warp_dir, &path, refs)?;
+        } else if let Ok(oid) = fs::read_to_string(&path)?.trim().parse() {
+            let name = path.strip_prefix(warp_dir).expect("ref is inside the warp directory");
+            refs.push((name.to_string_lossy().to_string(), oid));
+        }
+    }
+
+    Ok(())
+}
+
+// Reject names git would refuse for a ref component, such as `a..b`, `x~1`
+// or names with spaces or control characters.
+pub fn check_ref_name(name: &str) -> io::Result<()> {
+    let invalid = name.is_empty()
+        || name.starts_with('-')
+        || name.starts_with('/')
+        || name.ends_with('/')
+        || name.ends_with('.')
+        || name.ends_with(".lock")
+        || name.contains("..")
+        || name.contains("//")
+        || name.contains("@{")
+        || name == "@"
+        || name.split('/').any(|component| component.starts_with('.'))
+        || name.chars().any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c));
+
+    if invalid {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("'{}' is not a valid ref name", name)));
+    }
+    Ok(())
+}
+
+// Resolve a ref name (`HEAD`, a branch or tag) or an abbreviated object id.
+pub fn resolve_revision(store: &ObjectStore, refs: &Refs, name: &str) -> io::Result<ObjectId> {
+    match refs.resolve(name)? {
+        Some(oid) => Ok(oid),
+        None if name == "HEAD" => Err(io::Error::new(io::ErrorKind::NotFound, "HEAD does not point at a commit yet")),
+        None => store.resolve(name),
+    }
+}
+
+// The full names and targets of the named refs, or every ref when none are
+// named. `HEAD` is accepted as well.
+pub fn select_refs(refs: &Refs, names: &[String]) -> io::Result<Vec<(String, ObjectId)>> {
+    let all = refs.list()?;
+    if names.is_empty() {
+        return Ok(all);
+    }
+
+    let mut selected = Vec::new();
+    for name in names {
+        let full_name = [name.clone(), format!("refs/{}", name), format!("refs/tags/{}", name), format!("refs/heads/{}", name)]
+            .into_iter()
+            .find(|candidate| all.iter().any(|(ref_name, _)| ref_name == candidate));
+        match full_name {
+            Some(full_name) => {
+                let oid = all.iter().find(|(ref_name, _)| *ref_name == full_name).map(|(_, oid)| *oid).expect("ref was just found");
+                selected.push((full_name, oid));
+            },
+            None if name == "HEAD" => {
+                let oid = refs.read_head()?.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HEAD does not point at a commit yet"))?;
+                selected.push(("HEAD".to_string(), oid));
+            },
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, format!("unknown ref '{}'", name))),
+        }
+    }
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delete_packed_and_loose_refs() {
+        let dir = std::env::temp_dir().join(format!("warp-refs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let refs = Refs::new(dir.clone());
+        let first: ObjectId = "ce013625030ba8dba906f756967f9e9ca394464a".parse().unwrap();
+        let second: ObjectId = "4b825dc642cb6eb9a060e54bf8d69288fbee4904".parse().unwrap();
+
+        fs::write(dir.join("packed-refs"), format!(
+            "# pack-refs with: peeled\n{} refs/tags/v1\n^{}\n{} refs/tags/v2\n", first, second, second
+        )).unwrap();
+        refs.update("refs/tags/v3", &first).unwrap();
+
+        assert_eq!(refs.delete("refs/tags/v1").unwrap(), Some(first));
+        assert_eq!(refs.delete("refs/tags/v3").unwrap(), Some(first));
+        assert_eq!(refs.delete("refs/tags/v4").unwrap(), None);
+        assert_eq!(refs.list().unwrap(), vec![("refs/tags/v2".to_string(), second)]);
+
+        assert!(check_ref_name("v1.0").is_ok());
+        assert!(check_ref_name("v1..0").is_err());
+        assert!(check_ref_name("-v1").is_err());
+
+        assert!(refs.delete("refs/tags/../../packed-refs").is_err());
+        assert!(refs.delete("packed-refs").is_err());
+        fs::write(dir.join("refs/tags/notes"), "not a ref\n").unwrap();
+        assert_eq!(refs.delete("refs/tags/notes").unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(dir.join("refs/tags/notes").is_file());
+        assert!(dir.join("packed-refs").is_file());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,175 @@
+use std::io;
+
+use crate::auxiliary::glob_match;
+use crate::commit::Signature;
+use crate::object::{header_text, invalid_object, parse_headers, write_headers};
+use crate::object_id::ObjectId;
+use crate::object_store::{ObjectKind, ObjectStore};
+use crate::refs::{check_ref_name, Refs};
+
+
+const TAGGER: &str = "dummy author";
+const TAGGER_EMAIL: &str = "dummy@email.com";
+
+// An annotated tag object.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tag {
+    pub object: ObjectId,
+    pub kind: ObjectKind,
+    pub name: String,
+    // Very old tags were written without a tagger line.
+    pub tagger: Option<Signature>,
+    pub extra_headers: Vec<(String, Vec<u8>)>,
+    pub message: Vec<u8>,
+    // Whether a blank line ends the headers. Only a tag without a message
+    // can lack it.
+    pub separator: bool
+}
+
+impl Tag {
+    pub fn new(object: ObjectId, kind: ObjectKind, name: String, message: String) -> Self {
+        Tag {
+            object,
+            kind,
+            name,
+            tagger: Some(Signature::new(TAGGER, TAGGER_EMAIL)),
+            extra_headers: Vec::new(),
+            message: format!("{}\n", message).into_bytes(),
+            separator: true,
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let (headers, message) = parse_headers(bytes)?;
+        let separator = message.is_some();
+        let mut headers = headers.into_iter().peekable();
+
+        let object = match headers.next() {
+            Some((key, value)) if key == "object" => header_text(&key, &value)?.parse()?,
+            _ => return Err(invalid_object("tag does not start with an object")),
+        };
+        let kind = match headers.next() {
+            Some((key, value)) if key == "type" => header_text(&key, &value)?.parse()?,
+            _ => return Err(invalid_object("tag is missing a type")),
+        };
+        let name = match headers.next() {
+            Some((key, value)) if key == "tag" => header_text(&key, &value)?.to_string(),
+            _ => return Err(invalid_object("tag is missing a name")),
+        };
+        let tagger = match headers.next_if(|(key, _)| key == "tagger") {
+            Some((_, value)) => Some(Signature::parse(&value)?),
+            None => None,
+        };
+
+        Ok(Tag { object, kind, name, tagger, extra_headers: headers.collect(), message: message.unwrap_or_default(), separator })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut headers = vec![
+            ("object".to_string(), self.object.to_hex().into_bytes()),
+            ("type".to_string(), self.kind.to_string().into_bytes()),
+            ("tag".to_string(), self.name.clone().into_bytes()),
+        ];
+        if let Some(tagger) = &self.tagger {
+            headers.push(("tagger".to_string(), tagger.to_bytes()));
+        }
+        headers.extend(self.extra_headers.iter().cloned());
+
+        write_headers(&headers, &self.message, self.separator)
+    }
+
+    pub fn compress_to_object(&self) -> io::Result<ObjectId> {
+        ObjectStore::open()?.write(ObjectKind::Tag, &self.to_bytes())
+    }
+}
+
+// Point `refs/tags/<name>` at `object`. With a message the ref points at a
+// new annotated tag object instead. Returns the id the ref now holds.
+pub fn create_tag(store: &ObjectStore, refs: &Refs, name: &str, object: ObjectId, message: Option<String>, force: bool) -> io::Result<ObjectId> {
+    check_ref_name(name)?;
+    let ref_name = format!("refs/tags/{}", name);
+    if !force && refs.resolve(&ref_name)?.is_some() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("tag '{}' already exists", name)));
+    }
+
+    let oid = match message {
+        Some(message) => {
+            let (kind, _) = store.read(&object)?;
+            store.write(ObjectKind::Tag, &Tag::new(object, kind, name.to_string(), message).to_bytes())?
+        },
+        None => object,
+    };
+    refs.update(&ref_name, &oid)?;
+    Ok(oid)
+}
+
+// Names of the tags matching any of `patterns` (all tags if there are none),
+// limited to those pointing at `points_at` directly or through a tag object.
+pub fn list_tags(store: &ObjectStore, refs: &Refs, patterns: &[String], points_at: Option<ObjectId>) -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for (name, oid) in refs.list()? {
+        let Some(name) = name.strip_prefix("refs/tags/") else {
+            continue;
+        };
+        if !patterns.is_empty() && !patterns.iter().any(|pattern| glob_match(pattern.as_bytes(), name.as_bytes())) {
+            continue;
+        }
+        if let Some(target) = &points_at {
+            let peeled = match store.read(&oid)? {
+                (ObjectKind::Tag, bytes) => Some(Tag::parse(&bytes)?.object),
+                _ => None,
+            };
+            if oid != *target && peeled != Some(*target) {
+                continue;
+            }
+        }
+        names.push(name.to_string());
+    }
+    Ok(names)
+}
+
+// Remove `refs/tags/<name>` and return the id it pointed at.
+pub fn delete_tag(refs: &Refs, name: &str) -> io::Result<ObjectId> {
+    check_ref_name(name)?;
+    refs.delete(&format!("refs/tags/{}", name))?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("tag '{}' not found", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_id::ObjectFormat;
+    use std::fs;
+
+    #[test]
+    fn test_create_list_and_delete_tags() {
+        let dir = std::env::temp_dir().join(format!("warp-tag-{}", std::process::id()));
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        let store = ObjectStore::new(dir.join("objects"), ObjectFormat::Sha1);
+        let refs = Refs::new(dir.clone());
+        let first = store.write(ObjectKind::Blob, b"first\n").unwrap();
+        let second = store.write(ObjectKind::Blob, b"second\n").unwrap();
+
+        assert_eq!(create_tag(&store, &refs, "v1", first, None, false).unwrap(), first);
+        let annotated = create_tag(&store, &refs, "v2", first, Some("release".to_string()), false).unwrap();
+        assert_eq!(store.read(&annotated).unwrap().0, ObjectKind::Tag);
+        create_tag(&store, &refs, "next", second, None, false).unwrap();
+        assert_eq!(create_tag(&store, &refs, "v1", second, None, false).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert!(create_tag(&store, &refs, "../v3", first, None, false).is_err());
+
+        assert_eq!(list_tags(&store, &refs, &[], None).unwrap(), vec!["next", "v1", "v2"]);
+        assert_eq!(list_tags(&store, &refs, &["v*".to_string()], None).unwrap(), vec!["v1", "v2"]);
+        assert_eq!(list_tags(&store, &refs, &[], Some(first)).unwrap(), vec!["v1", "v2"]);
+        assert_eq!(list_tags(&store, &refs, &[], Some(second)).unwrap(), vec!["next"]);
+
+        fs::write(dir.join("config"), "").unwrap();
+        assert!(delete_tag(&refs, "../../config").is_err());
+        assert!(delete_tag(&refs, "../heads/next").is_err());
+        assert!(dir.join("config").is_file());
+        assert_eq!(delete_tag(&refs, "v1").unwrap(), first);
+        assert_eq!(delete_tag(&refs, "v1").unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(list_tags(&store, &refs, &[], None).unwrap(), vec!["next", "v2"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,14 +1,169 @@
-use crate::index::WarpIndex;
+use std::{collections::BTreeMap, io};
 
-struct Tree;
+use crate::object_id::{ObjectFormat, ObjectId};
+use crate::object_store::{ObjectKind, ObjectStore};
+
+// Every non-tree entry below a tree, keyed by its full slash-separated path,
+// with its mode and object id. Paths are raw bytes, as git stores them.
+pub type PathMap = BTreeMap<Vec<u8>, (u32, ObjectId)>;
+
+// A single `<mode> <name>\0<raw oid>` record of a tree object. The id is 20
+// bytes in SHA-1 repositories and 32 bytes in SHA-256 ones. Names are raw
+// bytes and need not be UTF-8.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreeEntry {
+    pub mode: u32,
+    // The mode as it was read, when that differs from how `mode` is written
+    // (old git versions wrote `040000` for trees). Kept so that re-writing
+    // the tree does not change its id.
+    pub raw_mode: Option<Vec<u8>>,
+    pub name: Vec<u8>,
+    pub oid: ObjectId
+}
+
+impl TreeEntry {
+    pub fn new(mode: u32, name: &[u8], oid: ObjectId) -> Self {
+        TreeEntry { mode, raw_mode: None, name: name.to_vec(), oid }
+    }
+
+    // The mode as it appears in the tree object.
+    pub fn mode_bytes(&self) -> Vec<u8> {
+        match &self.raw_mode {
+            Some(raw_mode) => raw_mode.clone(),
+            None => format!("{:o}", self.mode).into_bytes(),
+        }
+    }
+
+    // The kind of object this entry points at, derived from its mode.
+    pub fn kind(&self) -> ObjectKind {
+        match self.mode {
+            0o040000 => ObjectKind::Tree,
+            0o160000 => ObjectKind::Commit,
+            _ => ObjectKind::Blob,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tree {
+    pub entries: Vec<TreeEntry>
+}
 
 impl Tree {
-    pub fn create_extension(&self, index: WarpIndex) {
-        let index_entries = index.entries;
-        for entry in index_entries {
-            
+    pub fn parse(bytes: &[u8], format: ObjectFormat) -> io::Result<Self> {
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, format!("invalid tree: {}", reason));
+        let mut entries = Vec::new();
+        let mut rest = bytes;
+
+        while !rest.is_empty() {
+            let space = rest.iter().position(|&b| b == b' ').ok_or_else(|| invalid("missing mode separator"))?;
+            let mode_bytes = &rest[..space];
+            let mode = std::str::from_utf8(mode_bytes).ok()
+                .filter(|mode| !mode.is_empty() && mode.bytes().all(|b| matches!(b, b'0'..=b'7')))
+                .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+                .ok_or_else(|| invalid("malformed mode"))?;
+            let raw_mode = (format!("{:o}", mode).as_bytes() != mode_bytes).then(|| mode_bytes.to_vec());
+            rest = &rest[space + 1..];
+
+            let nul = rest.iter().position(|&b| b == 0).ok_or_else(|| invalid("missing name terminator"))?;
+            let name = rest[..nul].to_vec();
+            rest = &rest[nul + 1..];
+
+            let len = format.raw_len();
+            if rest.len() < len {
+                return Err(invalid("truncated object id"));
+            }
+            let oid = ObjectId::from_slice(&rest[..len]).expect("slice has the width of the format");
+            rest = &rest[len..];
+
+            entries.push(TreeEntry { mode, raw_mode, name, oid });
+        }
+
+        Ok(Tree { entries })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for entry in &self.entries {
+            bytes.extend(entry.mode_bytes());
+            bytes.push(b' ');
+            bytes.extend(&entry.name);
+            bytes.push(0);
+            bytes.extend(entry.oid.as_bytes());
+        }
+        bytes
+    }
+}
+
+// Flatten the tree `oid` and its subtrees into a `PathMap`.
+pub fn read_paths(store: &ObjectStore, oid: &ObjectId) -> io::Result<PathMap> {
+    let mut paths = PathMap::new();
+    collect_paths(store, oid, b"", &mut paths)?;
+    Ok(paths)
+}
+
+fn collect_paths(store: &ObjectStore, oid: &ObjectId, prefix: &[u8], paths: &mut PathMap) -> io::Result<()> {
+    let (kind, bytes) = store.read(oid)?;
+    if kind != ObjectKind::Tree {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is a {}, not a tree", oid, kind)));
+    }
+
+    for entry in Tree::parse(&bytes, store.format())?.entries {
+        let path = join_path(prefix, &entry.name);
+        if entry.kind() == ObjectKind::Tree {
+            collect_paths(store, &entry.oid, &path, paths)?;
+        } else {
+            paths.insert(path, (entry.mode, entry.oid));
         }
     }
+    Ok(())
 }
 
+// `prefix/name`, or just `name` at the top of the tree.
+pub fn join_path(prefix: &[u8], name: &[u8]) -> Vec<u8> {
+    if prefix.is_empty() {
+        return name.to_vec();
+    }
+    [prefix, b"/", name].concat()
+}
+
+// Write the trees for a `PathMap` bottom-up and return the root tree id.
+pub fn write_paths(store: &ObjectStore, paths: &PathMap) -> io::Result<ObjectId> {
+    let entries: Vec<(&[u8], u32, ObjectId)> = paths.iter().map(|(path, (mode, oid))| (path.as_slice(), *mode, *oid)).collect();
+    write_level(store, &entries)
+}
+
+// `entries` holds paths relative to the tree being written, in sorted order.
+fn write_level(store: &ObjectStore, entries: &[(&[u8], u32, ObjectId)]) -> io::Result<ObjectId> {
+    let mut tree = Tree { entries: Vec::new() };
+    let mut rest = entries;
 
+    while let Some((path, mode, oid)) = rest.first() {
+        match path.iter().position(|&b| b == b'/') {
+            None => {
+                tree.entries.push(TreeEntry::new(*mode, path, *oid));
+                rest = &rest[1..];
+            },
+            Some(slash) => {
+                let prefix = &path[..slash + 1];
+                let count = rest.iter().take_while(|(path, _, _)| path.starts_with(prefix)).count();
+                let children: Vec<(&[u8], u32, ObjectId)> = rest[..count].iter()
+                    .map(|(path, mode, oid)| (&path[prefix.len()..], *mode, *oid))
+                    .collect();
+                let oid = write_level(store, &children)?;
+                tree.entries.push(TreeEntry::new(0o040000, &prefix[..slash], oid));
+                rest = &rest[count..];
+            },
+        }
+    }
+
+    // Git orders entries as if directory names ended in '/'.
+    tree.entries.sort_by_cached_key(|entry| {
+        let mut key = entry.name.clone();
+        if entry.kind() == ObjectKind::Tree {
+            key.push(b'/');
+        }
+        key
+    });
+    store.write(ObjectKind::Tree, &tree.to_bytes())
+}